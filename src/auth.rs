@@ -0,0 +1,279 @@
+//! Finds the credentials to download from private repos and mirrors.
+//!
+//! Credentials for a host come from the `credentials` of the vessel
+//! configuration, then `GITHUB_TOKEN` for GitHub hosts and finally the
+//! `~/.netrc` file (or the file `NETRC` points to). Credentials are only
+//! ever sent in headers or passed to git through its environment, so they
+//! don't show up in logs or error messages.
+
+use crate::config::config;
+use anyhow::Result;
+use base64::Engine;
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// The credentials for a host in the vessel configuration, either a `token`
+/// or a `username` and `password`
+#[derive(Clone, Deserialize)]
+pub struct HostCredentials {
+    pub host: String,
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+impl fmt::Debug for HostCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostCredentials")
+            .field("host", &self.host)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) enum Credential {
+    Token(String),
+    Basic { username: String, password: String },
+}
+
+impl Credential {
+    /// The value of the `Authorization` header for this credential
+    pub(crate) fn header(&self) -> String {
+        match self {
+            Credential::Token(token) => format!("Bearer {token}"),
+            Credential::Basic { .. } => format!("Basic {}", self.basic()),
+        }
+    }
+
+    /// The credential as base64 encoded basic auth. Git hosts expect tokens
+    /// as the password of basic auth.
+    fn basic(&self) -> String {
+        let (username, password) = match self {
+            Credential::Token(token) => ("x-access-token", token.as_str()),
+            Credential::Basic { username, password } => (username.as_str(), password.as_str()),
+        };
+        base64::engine::general_purpose::STANDARD.encode(format!("{username}:{password}"))
+    }
+}
+
+impl From<&HostCredentials> for Option<Credential> {
+    fn from(credentials: &HostCredentials) -> Self {
+        match credentials {
+            HostCredentials {
+                token: Some(token), ..
+            } => Some(Credential::Token(token.clone())),
+            HostCredentials {
+                username: Some(username),
+                password: Some(password),
+                ..
+            } => Some(Credential::Basic {
+                username: username.clone(),
+                password: password.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A `machine` or `default` entry of a netrc file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct NetrcEntry {
+    /// `None` for the `default` entry
+    machine: Option<String>,
+    login: Option<String>,
+    password: Option<String>,
+}
+
+/// Parses the `machine`, `default`, `login` and `password` tokens of a netrc
+/// file, other tokens are skipped
+fn parse_netrc(source: &str) -> Vec<NetrcEntry> {
+    let mut entries: Vec<NetrcEntry> = vec![];
+    let mut tokens = source.split_whitespace();
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" | "default" => entries.push(NetrcEntry {
+                machine: if token == "machine" {
+                    tokens.next().map(str::to_string)
+                } else {
+                    None
+                },
+                login: None,
+                password: None,
+            }),
+            "login" | "password" | "account" => {
+                let value = tokens.next().map(str::to_string);
+                if let Some(entry) = entries.last_mut() {
+                    match token {
+                        "login" => entry.login = value,
+                        "password" => entry.password = value,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+fn netrc() -> &'static [NetrcEntry] {
+    static NETRC: OnceLock<Vec<NetrcEntry>> = OnceLock::new();
+    NETRC.get_or_init(|| {
+        let var = |name: &str| env::var_os(name).filter(|v| !v.is_empty());
+        var("NETRC")
+            .map(PathBuf::from)
+            .or_else(|| var("HOME").map(|home| PathBuf::from(home).join(".netrc")))
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|source| parse_netrc(&source))
+            .unwrap_or_default()
+    })
+}
+
+fn is_github(host: &str) -> bool {
+    host == "github.com" || host.ends_with(".github.com")
+}
+
+/// Looks up the credential for `host` in `configured`, the `github_token` and
+/// the `netrc` entries, in that order
+fn find_credential(
+    host: &str,
+    configured: &[HostCredentials],
+    github_token: Option<String>,
+    netrc: &[NetrcEntry],
+) -> Option<Credential> {
+    if let Some(credential) = configured
+        .iter()
+        .filter(|credentials| credentials.host == host)
+        .find_map(Option::<Credential>::from)
+    {
+        return Some(credential);
+    }
+    if is_github(host) {
+        if let Some(token) = github_token {
+            return Some(Credential::Token(token));
+        }
+    }
+    let entry = netrc
+        .iter()
+        .find(|entry| entry.machine.as_deref() == Some(host))
+        .or_else(|| netrc.iter().find(|entry| entry.machine.is_none()))?;
+    Some(Credential::Basic {
+        username: entry.login.clone().unwrap_or_default(),
+        password: entry.password.clone()?,
+    })
+}
+
+/// The credential to send along with requests to `url`
+pub(crate) fn credential_for(url: &str) -> Result<Option<Credential>> {
+    let host = match reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+    {
+        None => return Ok(None),
+        Some(host) => host,
+    };
+    let github_token = env::var("GITHUB_TOKEN").ok().filter(|t| !t.is_empty());
+    Ok(find_credential(
+        &host,
+        &config()?.credentials,
+        github_token,
+        netrc(),
+    ))
+}
+
+/// The git configuration that makes git send the credential for `url`
+pub(crate) fn git_config(url: &str) -> Result<Vec<(String, String)>> {
+    Ok(match credential_for(url)? {
+        None => vec![],
+        Some(credential) => extra_header(url, &credential).into_iter().collect(),
+    })
+}
+
+/// The git setting that sends `credential` along with requests to the host of
+/// `url`. It is scoped to that host, so git doesn't hand the credential to
+/// other hosts it talks to during the same command.
+fn extra_header(url: &str, credential: &Credential) -> Option<(String, String)> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    let scope = match url.port() {
+        None => format!("{}://{}/", url.scheme(), host),
+        Some(port) => format!("{}://{}:{}/", url.scheme(), host, port),
+    };
+    Some((
+        format!("http.{scope}.extraHeader"),
+        format!("Authorization: Basic {}", credential.basic()),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_finds_credentials() {
+        let netrc = parse_netrc(
+            "machine git.internal login ci password secret
+             default login anonymous password guest",
+        );
+        assert_eq!(netrc.len(), 2);
+        let configured = vec![HostCredentials {
+            host: "git.internal".to_string(),
+            token: Some("configured".to_string()),
+            username: None,
+            password: None,
+        }];
+        let token = || Some("github".to_string());
+
+        assert!(matches!(
+            find_credential("git.internal", &configured, token(), &netrc),
+            Some(Credential::Token(t)) if t == "configured"
+        ));
+        assert!(matches!(
+            find_credential("api.github.com", &configured, token(), &netrc),
+            Some(Credential::Token(t)) if t == "github"
+        ));
+        assert!(matches!(
+            find_credential("git.internal", &[], None, &netrc),
+            Some(Credential::Basic { username, password }) if username == "ci" && password == "secret"
+        ));
+        assert!(matches!(
+            find_credential("example.com", &[], None, &netrc),
+            Some(Credential::Basic { username, .. }) if username == "anonymous"
+        ));
+        assert!(find_credential("example.com", &[], None, &netrc[..1]).is_none());
+
+        assert_eq!(Credential::Token("abc".to_string()).header(), "Bearer abc");
+        assert_eq!(
+            Credential::Basic {
+                username: "ci".to_string(),
+                password: "secret".to_string()
+            }
+            .header(),
+            "Basic Y2k6c2VjcmV0"
+        );
+        assert!(!format!("{:?}", configured[0]).contains("configured"));
+    }
+
+    #[test]
+    fn it_scopes_git_credentials_to_the_host() {
+        let credential = Credential::Token("abc".to_string());
+        let (key, value) =
+            extra_header("https://git.internal:8443/org/repo.git", &credential).unwrap();
+        assert_eq!(key, "http.https://git.internal:8443/.extraHeader");
+        assert_eq!(
+            value,
+            format!("Authorization: Basic {}", credential.basic())
+        );
+        let (key, _) = extra_header("https://github.com/org/repo", &credential).unwrap();
+        assert_eq!(key, "http.https://github.com/.extraHeader");
+        assert!(extra_header("not a url", &credential).is_none());
+    }
+}
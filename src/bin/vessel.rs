@@ -2,8 +2,10 @@ use anyhow::Result;
 use fern::colors::ColoredLevelConfig;
 use fern::Output;
 use log::LevelFilter;
-use std::io::Write;
-use std::path::PathBuf;
+use serde::Serialize;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -12,29 +14,202 @@ struct Opts {
     /// Which file to read the package set from
     #[structopt(long, parse(from_os_str), default_value = "package-set.dhall")]
     package_set: PathBuf,
+    /// Only use packages and compilers that were downloaded already. Can also
+    /// be enabled by setting VESSEL_OFFLINE=1
+    #[structopt(long, global = true)]
+    offline: bool,
+    /// Download compilers for this platform instead of the host's, one of
+    /// x86_64-linux, aarch64-linux, x86_64-darwin or aarch64-darwin
+    #[structopt(long, global = true)]
+    platform: Option<vessel::Platform>,
+    /// Install compiler releases that no checksum is known for, neither a
+    /// compilerSha256 in vessel.dhall nor one published with the release
+    #[structopt(long, global = true)]
+    allow_unverified_compiler: bool,
     #[structopt(subcommand)]
     command: Command,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "Unknown format \"{s}\", expected \"text\" or \"json\""
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GraphFormat {
+    Dot,
+    Mermaid,
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            "mermaid" => Ok(GraphFormat::Mermaid),
+            "json" => Ok(GraphFormat::Json),
+            _ => Err(format!(
+                "Unknown format \"{s}\", expected \"dot\", \"mermaid\" or \"json\""
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffFormat {
+    Text,
+    Markdown,
+    Json,
+}
+
+impl FromStr for DiffFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(DiffFormat::Text),
+            "markdown" => Ok(DiffFormat::Markdown),
+            "json" => Ok(DiffFormat::Json),
+            _ => Err(format!(
+                "Unknown format \"{s}\", expected \"text\", \"markdown\" or \"json\""
+            )),
+        }
+    }
+}
+
 #[derive(Debug, StructOpt)]
 enum Command {
     /// Sets up the minimal project configuration
     Init,
     /// Installs all dependencies and prints a human readable summary
     Install {
-        #[structopt(short = "f")]
+        /// Re-download all packages, bypassing the package cache
+        #[structopt(short = "f", long)]
         force: bool,
+        /// Ignore the existing vessel.lock and re-resolve every package
+        #[structopt(long)]
+        update_lock: bool,
+        /// How many packages to download concurrently [default: 8]
+        #[structopt(short = "j", long)]
+        jobs: Option<usize>,
+        /// The output format, either "text" or "json"
+        #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+        format: Format,
+    },
+    /// Adds packages from the package set to vessel.dhall and installs them
+    Add {
+        /// The names of the packages to add
+        #[structopt(required = true)]
+        names: Vec<String>,
+    },
+    /// Removes packages from vessel.dhall
+    Remove {
+        /// The names of the packages to remove
+        #[structopt(required = true)]
+        names: Vec<String>,
+    },
+    /// Prints the dependency tree of the packages in vessel.dhall
+    Tree {
+        /// Only show the tree starting at this package
+        #[structopt(short = "p", long)]
+        package: Option<String>,
+        /// The maximum depth of the tree
+        #[structopt(short = "d", long)]
+        depth: Option<usize>,
+        /// Show the packages that depend on a package instead of its dependencies
+        #[structopt(short = "i", long)]
+        invert: bool,
+    },
+    /// Explains why a package is installed
+    Why {
+        /// The name of the package
+        name: String,
+    },
+    /// Exports the dependency graph of the package set
+    Graph {
+        /// The output format, one of "dot", "mermaid" or "json"
+        #[structopt(long, default_value = "dot", possible_values = &["dot", "mermaid", "json"])]
+        format: GraphFormat,
+        /// Only include the transitive dependencies of vessel.dhall
+        #[structopt(long)]
+        manifest: bool,
+    },
+    /// Lists newer tags for every package the project depends on
+    Outdated {
+        /// How many repos to query concurrently [default: 8]
+        #[structopt(short = "j", long)]
+        jobs: Option<usize>,
+        /// The output format, either "text" or "json"
+        #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+        format: Format,
+    },
+    /// Removes package versions and compilers from .vessel that aren't needed anymore
+    Gc {
+        /// Keep every package in the package set, not just the ones vessel.dhall needs
+        #[structopt(long)]
+        all: bool,
+        /// Only print what would be removed
+        #[structopt(long)]
+        dry_run: bool,
     },
     /// Outputs the import and hash for the latest vessel-package-set release.
     UpgradeSet {
         /// Use this tag instead of latest
         tag: Option<String>,
+        /// Update the `upstream` binding in the package set file instead of
+        /// printing it, and summarize the changed packages
+        #[structopt(long)]
+        write: bool,
+    },
+    /// Compares two package set files, or one package set file between git revisions
+    DiffSet {
+        /// The old package set file. With --from or --to, the package set file
+        /// to compare [default: the --package-set file]
+        #[structopt(parse(from_os_str))]
+        old: Option<PathBuf>,
+        /// The new package set file
+        #[structopt(parse(from_os_str))]
+        new: Option<PathBuf>,
+        /// Read the old package set from this git revision
+        #[structopt(long)]
+        from: Option<String>,
+        /// Read the new package set from this git revision instead of the working tree
+        #[structopt(long)]
+        to: Option<String>,
+        /// The output format, one of "text", "markdown" or "json"
+        #[structopt(long, default_value = "text", possible_values = &["text", "markdown", "json"])]
+        format: DiffFormat,
     },
     /// Installs all dependencies and outputs the package flags to be passed on
     /// to the Motoko compiler tools
-    Sources,
+    Sources {
+        /// The output format, either "text" or "json"
+        #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+        format: Format,
+    },
     /// Installs the compiler binaries and outputs a path to them
-    Bin,
+    Bin {
+        /// The output format, either "text" or "json"
+        #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+        format: Format,
+    },
     /// Verifies that every package in the package set builds successfully
     Verify {
         /// The version of the motoko compiler to use. Mutually exclusive with
@@ -57,13 +232,34 @@ enum Command {
         /// When specified fully compile the given package to get the wasm and validates it using wasm-validate
         #[structopt(long)]
         compile: bool,
+
+        /// The output format, either "text" or "json"
+        #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+        format: Format,
     },
 }
 
 fn setup_logger(opts: &Opts) -> Result<(), fern::InitError> {
-    let (log_level, out_channel): (LevelFilter, Output) = match opts.command {
-        Command::Sources | Command::Bin => (log::LevelFilter::Info, std::io::stderr().into()),
-        _ => (log::LevelFilter::Info, std::io::stdout().into()),
+    let (log_level, to_stderr): (LevelFilter, bool) = match opts.command {
+        Command::Sources { .. }
+        | Command::Bin { .. }
+        | Command::Tree { .. }
+        | Command::Why { .. }
+        | Command::Graph { .. }
+        | Command::DiffSet { .. }
+        | Command::Outdated {
+            format: Format::Json,
+            ..
+        }
+        | Command::Install {
+            format: Format::Json,
+            ..
+        }
+        | Command::Verify {
+            format: Format::Json,
+            ..
+        } => (log::LevelFilter::Info, true),
+        _ => (log::LevelFilter::Info, false),
     };
     let colors = ColoredLevelConfig::new();
     fern::Dispatch::new()
@@ -75,46 +271,351 @@ fn setup_logger(opts: &Opts) -> Result<(), fern::InitError> {
             ))
         })
         .level(log_level)
-        .chain(out_channel)
+        // Log lines are written above the progress status line
+        .chain(Output::call(move |record| {
+            vessel::suspend_status(|| {
+                if to_stderr {
+                    eprintln!("{}", record.args())
+                } else {
+                    println!("{}", record.args())
+                }
+            })
+        }))
         .apply()?;
+    vessel::set_interactive(std::io::stdout().is_terminal() && std::io::stderr().is_terminal());
     Ok(())
 }
 
-fn main() -> Result<()> {
+/// Runs `edit`, which changes the dependencies in vessel.dhall, and installs
+/// the packages needed afterwards. vessel.dhall is put back the way it was
+/// if either of them fails, so it never lists packages that can't be installed.
+fn edit_and_install(
+    vessel: &mut vessel::Vessel,
+    edit: impl FnOnce(&mut vessel::Vessel) -> Result<()>,
+) -> Result<()> {
+    let manifest_file = Path::new("vessel.dhall");
+    let source = std::fs::read_to_string(manifest_file)?;
+    let result = edit(vessel).and_then(|()| {
+        vessel.install_packages(&Default::default())?;
+        Ok(())
+    });
+    if result.is_err() {
+        std::fs::write(manifest_file, source)?;
+    }
+    result
+}
+
+/// Maps the errors reported by vessel to process exit codes, so scripts can
+/// tell them apart. Unexpected errors exit with 1.
+fn exit_code(err: &anyhow::Error) -> i32 {
+    use vessel::VesselError::*;
+    match err.downcast_ref::<vessel::VesselError>() {
+        None => 1,
+        Some(err) => match err {
+            ManifestParse { .. } | PackageSetParse { .. } | ConfigParse { .. } => 2,
+            InvalidName { .. } | InvalidVersion { .. } => 3,
+            UnknownPackage { .. } => 4,
+            DependencyCycle { .. } => 5,
+            LockOutdated { .. }
+            | HashMismatch { .. }
+            | CompilerHashMismatch { .. }
+            | CompilerChecksumMissing { .. }
+            | LockMismatch { .. } => 6,
+            NotAvailableOffline { .. } => 7,
+            DownloadFailed { .. } => 8,
+            GitFailed { .. } => 9,
+            CompilerFailed { .. } => 10,
+            VerificationFailed { .. } => 11,
+            VersionConflict { .. } => 12,
+            NoCompilerRelease { .. } => 13,
+            // Reports the first failure
+            PackagesFailed { errors, .. } => errors.first().map_or(1, |(_, err)| exit_code(err)),
+        },
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn print_outdated(packages: &[vessel::OutdatedPackage]) {
+    let rows: Vec<[&str; 4]> = packages
+        .iter()
+        .map(|package| {
+            [
+                package.name.as_str(),
+                package.current.as_str(),
+                package.compatible.as_deref().unwrap_or("-"),
+                package.latest.as_deref().unwrap_or("-"),
+            ]
+        })
+        .collect();
+    let header = ["Package", "Current", "Compatible", "Latest"];
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].len())
+                .chain([header[i].len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {err:?}");
+        std::process::exit(exit_code(&err));
+    }
+}
+
+fn run() -> Result<()> {
     let opts = Opts::from_args();
     setup_logger(&opts)?;
+    vessel::config()?;
+    let offline = opts.offline || std::env::var("VESSEL_OFFLINE").as_deref() == Ok("1");
 
     match opts.command {
-        Command::Init => vessel::init(),
-        Command::Install { force } => {
+        Command::Init => vessel::init(offline),
+        Command::Install {
+            force,
+            update_lock,
+            jobs,
+            format,
+        } => {
+            let mut vessel = vessel::Vessel::new(&opts.package_set)?;
+            vessel.offline = offline;
+            let packages = vessel.install_packages(&vessel::InstallOptions {
+                force,
+                update_lock,
+                jobs: jobs.unwrap_or(vessel::DEFAULT_JOBS),
+            })?;
+            if format == Format::Json {
+                print_json(&packages)?;
+            }
+            Ok(())
+        }
+        Command::Add { names } => {
+            let mut vessel = vessel::Vessel::new(&opts.package_set)?;
+            vessel.offline = offline;
+            edit_and_install(&mut vessel, |vessel| {
+                let added = vessel.add_dependencies(&names)?;
+                if !added.is_empty() {
+                    log::info!("Added {:?} to vessel.dhall", added);
+                }
+                Ok(())
+            })
+        }
+        Command::Remove { names } => {
+            let mut vessel = vessel::Vessel::new(&opts.package_set)?;
+            vessel.offline = offline;
+            // Updates the lock file
+            edit_and_install(&mut vessel, |vessel| {
+                let removed = vessel.remove_dependencies(&names)?;
+                if !removed.is_empty() {
+                    log::info!("Removed {:?} from vessel.dhall", removed);
+                }
+                Ok(())
+            })
+        }
+        Command::Tree {
+            package,
+            depth,
+            invert,
+        } => {
+            let mut vessel = vessel::Vessel::new(&opts.package_set)?;
+            vessel.offline = offline;
+            print!(
+                "{}",
+                vessel.dependency_tree(&vessel::TreeOptions {
+                    package,
+                    depth,
+                    invert,
+                })?
+            );
+            Ok(())
+        }
+        Command::Why { name } => {
             let vessel = vessel::Vessel::new(&opts.package_set)?;
-            let _ = vessel.install_packages(force)?;
+            let paths = vessel.why(&name)?;
+            if paths.is_empty() {
+                println!("\"{name}\" is not a dependency of any package in vessel.dhall");
+            } else {
+                println!("\"{name}\" is installed because of:");
+                for path in paths {
+                    if path.len() == 1 {
+                        println!("  {name} (listed in vessel.dhall)");
+                    } else {
+                        println!("  {}", path.join(" -> "));
+                    }
+                }
+            }
             Ok(())
         }
-        Command::UpgradeSet { tag } => {
+        Command::Graph { format, manifest } => {
+            let mut vessel = if manifest {
+                vessel::Vessel::new(&opts.package_set)?
+            } else {
+                vessel::Vessel::new_with_optional_manifest(&opts.package_set)?
+            };
+            vessel.offline = offline;
+            let graph = vessel.dependency_graph(manifest)?;
+            match format {
+                GraphFormat::Dot => print!("{}", graph.to_dot()),
+                GraphFormat::Mermaid => print!("{}", graph.to_mermaid()),
+                GraphFormat::Json => print_json(&graph)?,
+            }
+            Ok(())
+        }
+        Command::Outdated { jobs, format } => {
+            let mut vessel = vessel::Vessel::new(&opts.package_set)?;
+            vessel.offline = offline;
+            let packages = vessel.outdated(jobs.unwrap_or(vessel::DEFAULT_JOBS))?;
+            match format {
+                Format::Text => print_outdated(&packages),
+                Format::Json => print_json(&packages)?,
+            }
+            Ok(())
+        }
+        Command::Gc { all, dry_run } => {
+            let mut vessel = vessel::Vessel::new(&opts.package_set)?;
+            vessel.offline = offline;
+            let report = vessel.gc(&vessel::GcOptions {
+                keep_package_set: all,
+                dry_run,
+            })?;
+            for path in &report.removed {
+                if dry_run {
+                    println!("Would remove {}", path.display());
+                } else {
+                    println!("Removed {}", path.display());
+                }
+            }
+            println!(
+                "{} {}",
+                if dry_run {
+                    "Would reclaim"
+                } else {
+                    "Reclaimed"
+                },
+                vessel::format_size(report.reclaimed)
+            );
+            Ok(())
+        }
+        Command::UpgradeSet { tag, write } => {
             let (url, hash) = match tag {
                 None => vessel::fetch_latest_package_set()?,
                 Some(tag) => vessel::fetch_package_set(&tag)?,
             };
-            println!("let upstream =\n      {url} {hash}");
+            if !write {
+                println!("let upstream =\n      {url} {hash}");
+                return Ok(());
+            }
+            let changes = vessel::write_upstream(&opts.package_set, &url, &hash)?;
+            println!(
+                "Updated the upstream package set in {} to {}",
+                opts.package_set.display(),
+                url
+            );
+            match changes {
+                None => {}
+                Some(changes) if changes.is_empty() => println!("No packages changed"),
+                Some(changes) => {
+                    for change in changes {
+                        println!("{change}");
+                    }
+                }
+            }
             Ok(())
         }
-        Command::Bin => {
-            let vessel = vessel::Vessel::new(&opts.package_set)?;
+        Command::DiffSet {
+            old,
+            new,
+            from,
+            to,
+            format,
+        } => {
+            let (old, new) = if from.is_some() || to.is_some() {
+                if new.is_some() {
+                    return Err(anyhow::anyhow!(
+                        "Pass a single package set file when comparing git revisions"
+                    ));
+                }
+                let file = old.unwrap_or(opts.package_set);
+                let read = |rev: &Option<String>| match rev {
+                    None => vessel::read_package_set(&file),
+                    Some(rev) => vessel::read_package_set_at(&file, rev),
+                };
+                (read(&from)?, read(&to)?)
+            } else {
+                match (old, new) {
+                    (Some(old), Some(new)) => (
+                        vessel::read_package_set(&old)?,
+                        vessel::read_package_set(&new)?,
+                    ),
+                    _ => {
+                        return Err(anyhow::anyhow!(
+                            "Pass two package set files to compare, or use --from to compare against a git revision"
+                        ))
+                    }
+                }
+            };
+            let changes = vessel::diff_package_sets(&old, &new);
+            match format {
+                DiffFormat::Text if changes.is_empty() => println!("No packages changed"),
+                DiffFormat::Text => {
+                    for change in changes {
+                        println!("{change}");
+                    }
+                }
+                DiffFormat::Markdown => print!("{}", vessel::changes_to_markdown(&changes)),
+                DiffFormat::Json => print_json(&changes)?,
+            }
+            Ok(())
+        }
+        Command::Bin { format } => {
+            let mut vessel = vessel::Vessel::new(&opts.package_set)?;
+            vessel.offline = offline;
+            vessel.platform = opts.platform;
+            vessel.allow_unverified_compiler = opts.allow_unverified_compiler;
             let path = vessel.install_compiler()?;
-            print!("{}", path.display());
+            match format {
+                Format::Text => print!("{}", path.display()),
+                Format::Json => print_json(&serde_json::json!({
+                    "version": vessel.manifest.compiler,
+                    "path": path,
+                }))?,
+            }
             std::io::stdout().flush()?;
             Ok(())
         }
-        Command::Sources => {
-            let vessel = vessel::Vessel::new(&opts.package_set)?;
-            let sources = vessel
-                .install_packages(false)?
-                .into_iter()
-                .map(|(name, path)| format!("--package {} {}", name, path.display()))
-                .collect::<Vec<_>>()
-                .join(" ");
-            print!("{sources}");
+        Command::Sources { format } => {
+            let mut vessel = vessel::Vessel::new(&opts.package_set)?;
+            vessel.offline = offline;
+            let packages = vessel.install_packages(&Default::default())?;
+            match format {
+                Format::Text => {
+                    let sources = packages
+                        .into_iter()
+                        .map(|package| {
+                            format!("--package {} {}", package.name, package.path.display())
+                        })
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    print!("{sources}");
+                }
+                Format::Json => print_json(&packages)?,
+            }
             std::io::stdout().flush()?;
             Ok(())
         }
@@ -124,13 +625,22 @@ fn main() -> Result<()> {
             version,
             package,
             compile,
+            format,
         } => {
-            let vessel = vessel::Vessel::new_without_manifest(&opts.package_set)?;
+            let mut vessel = vessel::Vessel::new_without_manifest(&opts.package_set)?;
+            vessel.offline = offline;
+            vessel.resolve_versions(None, &Default::default())?;
             let moc = match (moc, version) {
                 (None, None) => PathBuf::from("moc"),
                 (Some(moc), None) => moc,
                 (None, Some(version)) => {
-                    let bin_path = vessel::download_compiler(&version)?;
+                    let bin_path = vessel::download_compiler(
+                        &version,
+                        None,
+                        opts.platform,
+                        offline,
+                        opts.allow_unverified_compiler,
+                    )?;
                     bin_path.join("moc")
                 }
                 (Some(_), Some(_)) => {
@@ -139,6 +649,17 @@ fn main() -> Result<()> {
                     ))
                 }
             };
+            if format == Format::Json {
+                let reports = match package {
+                    None => vessel.verify_all_reports(&moc, &moc_args, compile)?,
+                    Some(package) => {
+                        vec![vessel.verify_package_report(&moc, &moc_args, &package, compile)]
+                    }
+                };
+                print_json(&reports)?;
+                let action = if compile { "compile" } else { "verify" };
+                return vessel::VerificationReport::into_result(action, reports);
+            }
             if compile {
                 match package {
                     None => vessel.compile_all(&moc, &moc_args),
@@ -153,3 +674,239 @@ fn main() -> Result<()> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+    use vessel::VesselError::*;
+
+    /// Runs `f` in `dir` with the package cache in `dir` instead of the
+    /// user's. The working directory and the environment are shared by all
+    /// threads, so tests that depend on either take turns.
+    fn in_dir<R>(dir: &Path, f: impl FnOnce() -> R) -> R {
+        static ENV: Mutex<()> = Mutex::new(());
+        struct Restore(PathBuf, Option<std::ffi::OsString>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                std::env::set_current_dir(&self.0).unwrap();
+                match &self.1 {
+                    Some(previous) => std::env::set_var("VESSEL_HOME", previous),
+                    None => std::env::remove_var("VESSEL_HOME"),
+                }
+            }
+        }
+
+        let _lock = ENV.lock().unwrap_or_else(|err| err.into_inner());
+        let _restore = Restore(
+            std::env::current_dir().unwrap(),
+            std::env::var_os("VESSEL_HOME"),
+        );
+        std::env::set_current_dir(dir).unwrap();
+        std::env::set_var("VESSEL_HOME", dir.join(".cache"));
+        f()
+    }
+
+    #[test]
+    fn it_maps_errors_to_exit_codes() {
+        let dhall_error = || serde_dhall::from_str("{").parse::<u64>().unwrap_err();
+        let hash = || "sha256:abc".to_string();
+        let errors = vec![
+            (
+                ManifestParse {
+                    path: PathBuf::from("vessel.dhall"),
+                    source: dhall_error(),
+                },
+                2,
+            ),
+            (
+                PackageSetParse {
+                    path: PathBuf::from("package-set.dhall"),
+                    source: dhall_error(),
+                },
+                2,
+            ),
+            (
+                ConfigParse {
+                    path: PathBuf::from("config.dhall"),
+                    source: dhall_error(),
+                },
+                2,
+            ),
+            (
+                InvalidName {
+                    name: "..".to_string(),
+                },
+                3,
+            ),
+            (
+                InvalidVersion {
+                    version: "..".to_string(),
+                },
+                3,
+            ),
+            (
+                UnknownPackage {
+                    name: "bsae".to_string(),
+                    referrer: None,
+                    suggestion: Some("base".to_string()),
+                },
+                4,
+            ),
+            (
+                DependencyCycle {
+                    cycle: vec!["a".to_string(), "a".to_string()],
+                },
+                5,
+            ),
+            (
+                LockOutdated {
+                    package: "base".to_string(),
+                    locked_version: "v0.1.0".to_string(),
+                    locked_repo: "https://github.com/example/base".to_string(),
+                    version: "v0.2.0".to_string(),
+                    repo: "https://github.com/example/base".to_string(),
+                },
+                6,
+            ),
+            (
+                HashMismatch {
+                    package: "base".to_string(),
+                    expected: hash(),
+                    found: hash(),
+                },
+                6,
+            ),
+            (
+                CompilerHashMismatch {
+                    version: "0.9.3".to_string(),
+                    expected: hash(),
+                    found: hash(),
+                },
+                6,
+            ),
+            (
+                CompilerChecksumMissing {
+                    version: "0.9.3".to_string(),
+                    found: hash(),
+                },
+                6,
+            ),
+            (
+                LockMismatch {
+                    package: "base".to_string(),
+                    expected: hash(),
+                    found: hash(),
+                },
+                6,
+            ),
+            (
+                NotAvailableOffline {
+                    name: "base".to_string(),
+                    version: "v0.1.0".to_string(),
+                },
+                7,
+            ),
+            (
+                DownloadFailed {
+                    url: "https://example.com".to_string(),
+                    status: Some(502),
+                    details: String::new(),
+                },
+                8,
+            ),
+            (
+                GitFailed {
+                    repo: "https://github.com/example/base".to_string(),
+                    command: "git fetch".to_string(),
+                    stderr: String::new(),
+                },
+                9,
+            ),
+            (
+                CompilerFailed {
+                    package: "base".to_string(),
+                    stderr: String::new(),
+                },
+                10,
+            ),
+            (
+                VerificationFailed {
+                    package: "base".to_string(),
+                    stderr: String::new(),
+                },
+                11,
+            ),
+            (
+                VersionConflict {
+                    package: "base".to_string(),
+                    requirements: vec![],
+                    available: vec![],
+                },
+                12,
+            ),
+            (
+                NoCompilerRelease {
+                    version: "0.5.0".to_string(),
+                    platform: "Linux-aarch64".to_string(),
+                },
+                13,
+            ),
+        ];
+        for (err, code) in errors {
+            let description = err.to_string();
+            assert_eq!(exit_code(&err.into()), code, "{description}");
+        }
+
+        assert_eq!(exit_code(&anyhow::anyhow!("other")), 1);
+        let wrapped = anyhow::Error::new(NotAvailableOffline {
+            name: "base".to_string(),
+            version: "v0.1.0".to_string(),
+        })
+        .context("Failed to install");
+        assert_eq!(exit_code(&wrapped), 7);
+        let failed = PackagesFailed {
+            action: "install".to_string(),
+            errors: vec![(
+                "base".to_string(),
+                GitFailed {
+                    repo: "https://github.com/example/base".to_string(),
+                    command: "git fetch".to_string(),
+                    stderr: String::new(),
+                }
+                .into(),
+            )],
+        };
+        assert_eq!(exit_code(&failed.into()), 9);
+        let empty = PackagesFailed {
+            action: "install".to_string(),
+            errors: vec![],
+        };
+        assert_eq!(exit_code(&empty.into()), 1);
+    }
+
+    #[test]
+    fn it_restores_the_manifest_when_the_install_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        in_dir(dir.path(), || {
+            let manifest = "{ dependencies = [] : List Text, compiler = None Text }\n";
+            std::fs::write("vessel.dhall", manifest).unwrap();
+            std::fs::write(
+                "package-set.dhall",
+                r#"[ { name = "lib", repo = "https://github.com/example/lib", version = "v1.0.0", dependencies = [] : List Text } ]"#,
+            )
+            .unwrap();
+
+            let mut vessel = vessel::Vessel::new(Path::new("package-set.dhall")).unwrap();
+            // Nothing is installed, so installing offline fails
+            vessel.offline = true;
+            let err = edit_and_install(&mut vessel, |vessel| {
+                vessel.add_dependencies(&["lib".to_string()])?;
+                Ok(())
+            })
+            .unwrap_err();
+            assert_eq!(exit_code(&err), 7);
+            assert_eq!(std::fs::read_to_string("vessel.dhall").unwrap(), manifest);
+        });
+    }
+}
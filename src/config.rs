@@ -0,0 +1,247 @@
+//! The user-level vessel configuration.
+//!
+//! The configuration is read from the Dhall file at `VESSEL_CONFIG`, or
+//! `vessel/config.dhall` inside `XDG_CONFIG_HOME` (`~/.config` by default).
+//! It configures mirrors and URL rewrite rules, so vessel can download
+//! packages, compilers and package sets without access to GitHub, the
+//! credentials for private hosts and the network settings:
+//!
+//! ```dhall
+//! { mirrors =
+//!   { packages = Some "http://mirror.internal/github"
+//!   , compiler = Some "file:///srv/motoko"
+//!   , packageSets = None Text
+//!   }
+//! , rewrites =
+//!   [ { prefix = "https://gitlab.com/", replacement = "http://mirror.internal/gitlab/" } ]
+//! , credentials = [ { host = "mirror.internal", token = Some env:MIRROR_TOKEN as Text } ]
+//! , network = { timeout = Some 120, retries = Some 5, proxy = Some "http://proxy.internal:3128" }
+//! }
+//! ```
+
+use crate::auth::HostCredentials;
+use crate::{Url, VesselError};
+use anyhow::Result;
+use serde::Deserialize;
+use std::env;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Mirrors of the places vessel downloads from. A mirror replaces the base
+/// URL and needs to serve the same paths below it.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Mirrors {
+    /// Replaces https://github.com/ for package repos
+    #[serde(default)]
+    pub packages: Option<Url>,
+    /// Replaces the download locations of compiler releases
+    #[serde(default)]
+    pub compiler: Option<Url>,
+    /// Replaces the download location of vessel-package-set releases
+    #[serde(default, rename = "packageSets")]
+    pub package_sets: Option<Url>,
+}
+
+/// Replaces `prefix` at the start of a URL with `replacement`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Rewrite {
+    pub prefix: Url,
+    pub replacement: Url,
+}
+
+/// How vessel talks to the network, these settings apply to every download
+/// and git command
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Network {
+    /// Seconds to wait for a connection, 30 by default
+    #[serde(default, rename = "connectTimeout")]
+    pub connect_timeout: Option<u64>,
+    /// Seconds to wait for a response or the next chunk of it, 60 by default
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// How often to retry requests that failed with a network error or a
+    /// retryable status like 502, 3 by default. Only sending the request is
+    /// retried, not reading the response body or git commands.
+    #[serde(default)]
+    pub retries: Option<u32>,
+    /// The HTTP(S) proxy to use instead of the one in `HTTPS_PROXY` or `HTTP_PROXY`
+    #[serde(default)]
+    pub proxy: Option<Url>,
+    /// Comma separated hosts to reach without the proxy
+    #[serde(default, rename = "noProxy")]
+    pub no_proxy: Option<String>,
+    /// A PEM file with the CA certificates to trust, replaces the built-in ones
+    #[serde(default, rename = "caBundle")]
+    pub ca_bundle: Option<PathBuf>,
+}
+
+impl Network {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout.unwrap_or(30))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(60))
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(3)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub mirrors: Mirrors,
+    #[serde(default)]
+    pub rewrites: Vec<Rewrite>,
+    #[serde(default)]
+    pub credentials: Vec<HostCredentials>,
+    #[serde(default)]
+    pub network: Network,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The configuration of this process, read from `config_file` on first use
+pub fn config() -> Result<&'static Config> {
+    if let Some(config) = CONFIG.get() {
+        return Ok(config);
+    }
+    let config = match config_file() {
+        Some(path) if path.exists() => Config::read(path)?,
+        _ => Config::default(),
+    };
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+/// Rewrites `url` according to the configuration of this process
+pub(crate) fn rewrite_url(url: &str) -> Result<Url> {
+    let rewritten = config()?.rewrite(url);
+    if rewritten != url {
+        log::debug!("Rewrote {} to {}", url, rewritten);
+    }
+    Ok(rewritten)
+}
+
+/// Where the configuration file is expected. `VESSEL_CONFIG` takes precedence
+/// over `XDG_CONFIG_HOME`.
+pub fn config_file() -> Option<PathBuf> {
+    let var = |name: &str| env::var_os(name).filter(|v| !v.is_empty());
+    var("VESSEL_CONFIG").map(PathBuf::from).or_else(|| {
+        var("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| var("HOME").map(|dir| PathBuf::from(dir).join(".config")))
+            .map(|dir| dir.join("vessel").join("config.dhall"))
+    })
+}
+
+impl Config {
+    pub fn read(path: PathBuf) -> Result<Config> {
+        // Without a static type annotation all fields stay optional
+        Ok(serde_dhall::from_file(&path)
+            .parse()
+            .map_err(|source| VesselError::ConfigParse { path, source })?)
+    }
+
+    /// The rewrite rules, with the mirrors turned into rules for the base URLs
+    /// they replace unless there's an explicit rewrite for it
+    pub(crate) fn rules(&self) -> Vec<Rewrite> {
+        let mut rules = self.rewrites.clone();
+        let mut mirror = |base: &str, mirror: &Option<Url>| {
+            if rules.iter().any(|rule| rule.prefix == base) {
+                return;
+            }
+            if let Some(mirror) = mirror {
+                rules.push(Rewrite {
+                    prefix: base.to_string(),
+                    replacement: format!("{}/", mirror.trim_end_matches('/')),
+                })
+            }
+        };
+        mirror("https://github.com/", &self.mirrors.packages);
+        mirror(
+            "https://github.com/dfinity/motoko/releases/download/",
+            &self.mirrors.compiler,
+        );
+        mirror(
+            "https://download.dfinity.systems/motoko/",
+            &self.mirrors.compiler,
+        );
+        mirror(
+            "https://github.com/dfinity/vessel-package-set/releases/download/",
+            &self.mirrors.package_sets,
+        );
+        rules
+    }
+
+    /// Applies the rule with the longest matching prefix to `url`. Explicit
+    /// rewrites win over mirrors for the same prefix.
+    pub fn rewrite(&self, url: &str) -> Url {
+        let mut best: Option<Rewrite> = None;
+        for rule in self.rules() {
+            if url.starts_with(&rule.prefix)
+                && best
+                    .as_ref()
+                    .is_none_or(|best| rule.prefix.len() > best.prefix.len())
+            {
+                best = Some(rule);
+            }
+        }
+        match best {
+            None => url.to_string(),
+            Some(rule) => format!("{}{}", rule.replacement, &url[rule.prefix.len()..]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_rewrites_urls() {
+        let config: Config = serde_dhall::from_str(
+            r#"{ mirrors =
+                 { packages = Some "http://mirror.internal/github"
+                 , compiler = Some "file:///srv/motoko/"
+                 }
+               , rewrites =
+                 [ { prefix = "https://github.com/dfinity/", replacement = "http://mirror.internal/dfinity/" }
+                 , { prefix = "https://gitlab.com/", replacement = "http://mirror.internal/gitlab/" }
+                 ]
+               }"#,
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(
+            config.rewrite("https://github.com/example/base"),
+            "http://mirror.internal/github/example/base"
+        );
+        assert_eq!(
+            config.rewrite("https://github.com/dfinity/motoko-base"),
+            "http://mirror.internal/dfinity/motoko-base"
+        );
+        assert_eq!(
+            config.rewrite("https://github.com/dfinity/motoko/releases/download/0.9.3/motoko-Linux-x86_64-0.9.3.tar.gz"),
+            "file:///srv/motoko/0.9.3/motoko-Linux-x86_64-0.9.3.tar.gz"
+        );
+        assert_eq!(
+            config.rewrite("https://gitlab.com/example/json"),
+            "http://mirror.internal/gitlab/example/json"
+        );
+        assert_eq!(
+            config.rewrite("https://example.com/base"),
+            "https://example.com/base"
+        );
+
+        let empty: Config = serde_dhall::from_str("{=}").parse().unwrap();
+        assert_eq!(
+            empty.rewrite("https://github.com/example/base"),
+            "https://github.com/example/base"
+        );
+        assert_eq!(empty.network.timeout(), Duration::from_secs(60));
+        assert_eq!(empty.network.retries(), 3);
+    }
+}
@@ -0,0 +1,234 @@
+//! Compares two package sets
+
+use crate::{Name, Package, Tag, Url};
+use serde::Serialize;
+use std::fmt;
+
+/// How a package differs between two package sets
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum PackageChange {
+    Added {
+        name: Name,
+        version: Tag,
+        repo: Url,
+    },
+    Removed {
+        name: Name,
+        version: Tag,
+        repo: Url,
+    },
+    /// The version, the repo or the dependencies of the package changed
+    Updated {
+        name: Name,
+        old_version: Tag,
+        new_version: Tag,
+        old_repo: Url,
+        new_repo: Url,
+        added_dependencies: Vec<Name>,
+        removed_dependencies: Vec<Name>,
+    },
+}
+
+impl PackageChange {
+    pub fn name(&self) -> &str {
+        match self {
+            PackageChange::Added { name, .. }
+            | PackageChange::Removed { name, .. }
+            | PackageChange::Updated { name, .. } => name,
+        }
+    }
+
+    /// Describes the changed repo and dependencies of an updated package
+    fn details(&self) -> Vec<String> {
+        let mut details = vec![];
+        if let PackageChange::Updated {
+            old_repo,
+            new_repo,
+            added_dependencies,
+            removed_dependencies,
+            ..
+        } = self
+        {
+            if old_repo != new_repo {
+                details.push(format!("moved from {old_repo} to {new_repo}"));
+            }
+            let dependencies: Vec<String> = added_dependencies
+                .iter()
+                .map(|dep| format!("+{dep}"))
+                .chain(removed_dependencies.iter().map(|dep| format!("-{dep}")))
+                .collect();
+            if !dependencies.is_empty() {
+                details.push(format!("dependencies {}", dependencies.join(", ")));
+            }
+        }
+        details
+    }
+}
+
+impl fmt::Display for PackageChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackageChange::Added { name, version, .. } => write!(f, "+ {name} {version}"),
+            PackageChange::Removed { name, version, .. } => write!(f, "- {name} {version}"),
+            PackageChange::Updated {
+                name,
+                old_version,
+                new_version,
+                ..
+            } => {
+                write!(f, "~ {name} {old_version} -> {new_version}")?;
+                let details = self.details();
+                if !details.is_empty() {
+                    write!(f, " ({})", details.join("; "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Lists the packages that were added, removed or updated between `old` and
+/// `new`, sorted by name
+pub fn diff_package_sets(old: &[Package], new: &[Package]) -> Vec<PackageChange> {
+    let mut changes = vec![];
+    for package in old {
+        match new.iter().find(|p| p.name == package.name) {
+            None => changes.push(PackageChange::Removed {
+                name: package.name.clone(),
+                version: package.version.clone(),
+                repo: package.repo.clone(),
+            }),
+            Some(updated) => {
+                let added_dependencies = missing_from(&updated.dependencies, &package.dependencies);
+                let removed_dependencies =
+                    missing_from(&package.dependencies, &updated.dependencies);
+                if updated.version != package.version
+                    || updated.repo != package.repo
+                    || !added_dependencies.is_empty()
+                    || !removed_dependencies.is_empty()
+                {
+                    changes.push(PackageChange::Updated {
+                        name: package.name.clone(),
+                        old_version: package.version.clone(),
+                        new_version: updated.version.clone(),
+                        old_repo: package.repo.clone(),
+                        new_repo: updated.repo.clone(),
+                        added_dependencies,
+                        removed_dependencies,
+                    })
+                }
+            }
+        }
+    }
+    for package in new {
+        if !old.iter().any(|p| p.name == package.name) {
+            changes.push(PackageChange::Added {
+                name: package.name.clone(),
+                version: package.version.clone(),
+                repo: package.repo.clone(),
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.name().cmp(b.name()));
+    changes
+}
+
+/// The names in `names` that aren't in `other`, sorted
+fn missing_from(names: &[Name], other: &[Name]) -> Vec<Name> {
+    let mut missing: Vec<Name> = names
+        .iter()
+        .filter(|name| !other.contains(name))
+        .cloned()
+        .collect();
+    missing.sort();
+    missing.dedup();
+    missing
+}
+
+/// Renders `changes` as a Markdown table, for example to post it on a pull request
+pub fn changes_to_markdown(changes: &[PackageChange]) -> String {
+    if changes.is_empty() {
+        return "No packages changed.\n".to_string();
+    }
+    let escape = |s: &str| s.replace('|', "\\|");
+    let mut out =
+        String::from("| Package | Change | Version | Details |\n| --- | --- | --- | --- |\n");
+    for change in changes {
+        let (kind, version) = match change {
+            PackageChange::Added { version, .. } => ("added", format!("`{version}`")),
+            PackageChange::Removed { version, .. } => ("removed", format!("`{version}`")),
+            PackageChange::Updated {
+                old_version,
+                new_version,
+                ..
+            } if old_version != new_version => {
+                ("updated", format!("`{old_version}` → `{new_version}`"))
+            }
+            PackageChange::Updated { new_version, .. } => ("updated", format!("`{new_version}`")),
+        };
+        out.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            escape(change.name()),
+            kind,
+            escape(&version),
+            escape(&change.details().join("; "))
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util;
+
+    fn mk_package(name: &str, version: &str) -> Package {
+        Package {
+            version: version.to_string(),
+            ..test_util::mk_package(name, vec!["base"])
+        }
+    }
+
+    #[test]
+    fn it_diffs_package_sets() {
+        let old = vec![
+            mk_package("base", "v0.1.0"),
+            mk_package("json", "v1.0.0"),
+            mk_package("http", "v1.0.0"),
+        ];
+        let mut moved = mk_package("http", "v1.0.0");
+        moved.repo = "https://github.com/someone/http".to_string();
+        moved.dependencies = vec!["json".to_string()];
+        let new = vec![
+            mk_package("base", "v0.2.0"),
+            moved,
+            mk_package("matchers", "v1.0.0"),
+        ];
+        let changes: Vec<String> = diff_package_sets(&old, &new)
+            .iter()
+            .map(|change| change.to_string())
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                "~ base v0.1.0 -> v0.2.0",
+                "~ http v1.0.0 -> v1.0.0 (moved from https://github.com/example/http to https://github.com/someone/http; dependencies +json, -base)",
+                "- json v1.0.0",
+                "+ matchers v1.0.0",
+            ]
+        );
+        assert!(diff_package_sets(&old, &old).is_empty());
+
+        assert_eq!(
+            changes_to_markdown(&diff_package_sets(&old, &new)),
+            "| Package | Change | Version | Details |
+| --- | --- | --- | --- |
+| base | updated | `v0.1.0` → `v0.2.0` |  |
+| http | updated | `v1.0.0` | moved from https://github.com/example/http to https://github.com/someone/http; dependencies +json, -base |
+| json | removed | `v1.0.0` |  |
+| matchers | added | `v1.0.0` |  |
+"
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use crate::{Hash, Name, Tag, Url};
+use std::fmt;
+use std::path::PathBuf;
+
+/// The errors reported by vessel. Functions in this crate return
+/// `anyhow::Result`, use `anyhow::Error::downcast_ref::<VesselError>` to find
+/// out what went wrong.
+#[derive(Debug)]
+pub enum VesselError {
+    /// The manifest file could not be read or doesn't have the expected type
+    ManifestParse {
+        path: PathBuf,
+        source: serde_dhall::Error,
+    },
+    /// The package set file could not be read or doesn't have the expected type
+    PackageSetParse {
+        path: PathBuf,
+        source: serde_dhall::Error,
+    },
+    /// The vessel configuration file could not be read or doesn't have the
+    /// expected type
+    ConfigParse {
+        path: PathBuf,
+        source: serde_dhall::Error,
+    },
+    /// A package name that can't be used as a directory name
+    InvalidName { name: Name },
+    /// A package or compiler version that can't be used as a directory name
+    InvalidVersion { version: Tag },
+    /// A package that isn't part of the package set. `referrer` is the package
+    /// that depends on it, if any.
+    UnknownPackage {
+        name: Name,
+        referrer: Option<Name>,
+        suggestion: Option<Name>,
+    },
+    /// The dependencies of a package contain a cycle, the path starts and ends
+    /// with the same package
+    DependencyCycle { cycle: Vec<Name> },
+    /// No tag of a package satisfies all version ranges it is required at.
+    /// `requirements` describes each range and where it comes from.
+    VersionConflict {
+        package: Name,
+        requirements: Vec<String>,
+        available: Vec<Tag>,
+    },
+    /// The lock file pins a package to a different version or repo than the
+    /// package set
+    LockOutdated {
+        package: Name,
+        locked_version: Tag,
+        locked_repo: Url,
+        version: Tag,
+        repo: Url,
+    },
+    /// The sources of a package don't match the `sha256` in the package set
+    HashMismatch {
+        package: Name,
+        expected: Hash,
+        found: Hash,
+    },
+    /// The downloaded compiler release doesn't match its checksum
+    CompilerHashMismatch {
+        version: Tag,
+        expected: Hash,
+        found: Hash,
+    },
+    /// No checksum is known for the downloaded compiler release, so it can't be
+    /// verified. `found` is the checksum of the download.
+    CompilerChecksumMissing { version: Tag, found: Hash },
+    /// The compiler at `version` wasn't released for `platform`
+    NoCompilerRelease { version: Tag, platform: String },
+    /// The sources of a package don't match the hash in the lock file
+    LockMismatch {
+        package: Name,
+        expected: Hash,
+        found: Hash,
+    },
+    /// A package or compiler is needed, but vessel is running in offline mode
+    NotAvailableOffline { name: Name, version: Tag },
+    /// A HTTP request failed
+    DownloadFailed {
+        url: Url,
+        status: Option<u16>,
+        details: String,
+    },
+    /// A git command failed
+    GitFailed {
+        repo: Url,
+        command: String,
+        stderr: String,
+    },
+    /// Compiling a package or validating its WASM output failed
+    CompilerFailed { package: Name, stderr: String },
+    /// Checking a package with `moc --check` failed
+    VerificationFailed { package: Name, stderr: String },
+    /// Some packages failed to install, verify or compile
+    PackagesFailed {
+        action: String,
+        errors: Vec<(Name, anyhow::Error)>,
+    },
+}
+
+impl fmt::Display for VesselError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VesselError::ManifestParse { path, .. } => {
+                write!(f, "Failed to parse the {} file", path.display())
+            }
+            VesselError::PackageSetParse { path, .. } => {
+                write!(f, "Failed to parse the package set file {}", path.display())
+            }
+            VesselError::ConfigParse { path, .. } => {
+                write!(f, "Failed to parse the configuration file {}", path.display())
+            }
+            VesselError::InvalidName { name } => write!(f, "Invalid package name: `{name}`"),
+            VesselError::InvalidVersion { version } => {
+                write!(f, "Invalid version string: `{version}`")
+            }
+            VesselError::UnknownPackage {
+                name,
+                referrer,
+                suggestion,
+            } => {
+                match referrer {
+                    None => write!(f, "Package \"{name}\" wasn't specified in the package set")?,
+                    Some(referrer) => write!(
+                        f,
+                        "Package \"{referrer}\" depends on \"{name}\", which wasn't specified in the package set"
+                    )?,
+                }
+                if let Some(suggestion) = suggestion {
+                    write!(f, ". Did you mean \"{suggestion}\"?")?;
+                }
+                Ok(())
+            }
+            VesselError::DependencyCycle { cycle } => write!(
+                f,
+                "Found a dependency cycle in the package set: {}",
+                cycle.join(" -> ")
+            ),
+            VesselError::VersionConflict {
+                package,
+                requirements,
+                available,
+            } => {
+                write!(
+                    f,
+                    "No version of \"{package}\" satisfies every requirement:"
+                )?;
+                for requirement in requirements {
+                    write!(f, "\n  {requirement}")?;
+                }
+                if available.is_empty() {
+                    write!(f, "\nThe repo has no semver tags")
+                } else {
+                    write!(f, "\nAvailable versions: {}", available.join(", "))
+                }
+            }
+            VesselError::LockOutdated {
+                package,
+                locked_version,
+                locked_repo,
+                version,
+                repo,
+            } => write!(
+                f,
+                "{} pins \"{}\" to version \"{}\" from {}, but the package set specifies version \"{}\" from {}.\nRun `vessel install --update-lock` to re-resolve the lock file.",
+                crate::LOCK_FILE,
+                package,
+                locked_version,
+                locked_repo,
+                version,
+                repo
+            ),
+            VesselError::HashMismatch {
+                package,
+                expected,
+                found,
+            } => write!(
+                f,
+                "The sources of \"{package}\" do not match the sha256 given in the package set.\nExpected: {expected}\nFound:    {found}"
+            ),
+            VesselError::CompilerHashMismatch {
+                version,
+                expected,
+                found,
+            } => write!(
+                f,
+                "The download of version {version} of the Motoko compiler does not match its checksum.\nExpected: {expected}\nFound:    {found}"
+            ),
+            VesselError::CompilerChecksumMissing { version, found } => write!(
+                f,
+                "No checksum is known for version {version} of the Motoko compiler, so its download can't be verified.\nAdd `compilerSha256 = Some \"{found}\"` to vessel.dhall if you trust it, or pass --allow-unverified-compiler"
+            ),
+            VesselError::NoCompilerRelease { version, platform } => {
+                write!(
+                    f,
+                    "Version {version} of the Motoko compiler was not released for {platform}"
+                )?;
+                if platform == "aarch64-darwin" {
+                    write!(f, ".\nUse `--platform x86_64-darwin` to run the x86_64 release through Rosetta instead")?;
+                }
+                Ok(())
+            }
+            VesselError::LockMismatch {
+                package,
+                expected,
+                found,
+            } => write!(
+                f,
+                "The sources of \"{}\" do not match {}.\nExpected: {}\nFound:    {}",
+                package,
+                crate::LOCK_FILE,
+                expected,
+                found
+            ),
+            VesselError::NotAvailableOffline { name, version } => write!(
+                f,
+                "\"{name}\" at version {version} has not been downloaded yet and vessel is running in offline mode"
+            ),
+            VesselError::DownloadFailed {
+                url,
+                status,
+                details,
+            } => {
+                write!(f, "Failed to download {url}")?;
+                if let Some(status) = status {
+                    write!(f, ", with status {status}")?;
+                }
+                write!(f, "\n\nDetails: {details}")
+            }
+            VesselError::GitFailed {
+                repo,
+                command,
+                stderr,
+            } => write!(
+                f,
+                "Failed to run `git {command}` for the repo at: {repo}\nwith:\n{stderr}"
+            ),
+            VesselError::CompilerFailed { package, stderr } => {
+                write!(f, "Failed to compile \"{package}\" with:\n{stderr}")
+            }
+            VesselError::VerificationFailed { package, stderr } => {
+                write!(f, "Failed to verify \"{package}\" with:\n{stderr}")
+            }
+            VesselError::PackagesFailed { action, errors } => {
+                write!(
+                    f,
+                    "Failed to {}: {:?}",
+                    action,
+                    errors.iter().map(|(name, _)| name).collect::<Vec<_>>()
+                )?;
+                for (name, err) in errors {
+                    write!(f, "\n\n\"{name}\": {err:#}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for VesselError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VesselError::ManifestParse { source, .. }
+            | VesselError::PackageSetParse { source, .. }
+            | VesselError::ConfigParse { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
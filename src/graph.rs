@@ -0,0 +1,167 @@
+//! Exports the package dependency graph for `vessel graph`
+
+use crate::{Name, Package, Tag, Url};
+use serde::Serialize;
+
+/// A package in a `DependencyGraph`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GraphNode {
+    pub name: Name,
+    pub version: Tag,
+    pub repo: Url,
+    /// Whether the manifest depends on this package directly
+    pub direct: bool,
+}
+
+/// `from` depends on `to`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GraphEdge {
+    pub from: Name,
+    pub to: Name,
+}
+
+/// The dependency graph between packages, sorted by name
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct DependencyGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl DependencyGraph {
+    /// Builds the graph between `packages`, marking the `direct` dependencies.
+    /// Dependencies outside of `packages` are left out.
+    pub(crate) fn new(packages: &[&Package], direct: &[Name]) -> DependencyGraph {
+        let mut packages = packages.to_vec();
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        let nodes = packages
+            .iter()
+            .map(|package| GraphNode {
+                name: package.name.clone(),
+                version: package.version.clone(),
+                repo: package.repo.clone(),
+                direct: direct.contains(&package.name),
+            })
+            .collect();
+        let mut edges = vec![];
+        for package in &packages {
+            let mut deps: Vec<&Name> = package
+                .dependencies
+                .iter()
+                .filter(|dep| packages.iter().any(|p| &&p.name == dep))
+                .collect();
+            deps.sort();
+            deps.dedup();
+            edges.extend(deps.into_iter().map(|dep| GraphEdge {
+                from: package.name.clone(),
+                to: dep.clone(),
+            }));
+        }
+        DependencyGraph { nodes, edges }
+    }
+
+    /// Renders the graph in the Graphviz DOT language
+    pub fn to_dot(&self) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut out = String::from("digraph packages {\n  node [shape=box];\n");
+        for node in &self.nodes {
+            let label = format!("{}\n{}\n{}", node.name, node.version, node.repo);
+            out.push_str(&format!(
+                "  {} [label={}{}];\n",
+                quote(&node.name),
+                quote(&label).replace('\n', "\\n"),
+                if node.direct {
+                    ", style=\"bold,filled\", fillcolor=lightblue"
+                } else {
+                    ""
+                }
+            ));
+        }
+        for edge in &self.edges {
+            out.push_str(&format!(
+                "  {} -> {};\n",
+                quote(&edge.from),
+                quote(&edge.to)
+            ));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Renders the graph as a Mermaid flowchart
+    pub fn to_mermaid(&self) -> String {
+        // Package names aren't necessarily valid Mermaid ids, so nodes are
+        // referred to by their index
+        let id = |name: &str| {
+            self.nodes
+                .iter()
+                .position(|node| node.name == name)
+                .map_or_else(|| name.to_string(), |i| format!("n{i}"))
+        };
+        let escape = |s: &str| s.replace('"', "#quot;");
+        let mut out = String::from("graph TD\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            out.push_str(&format!(
+                "  n{}[\"{}<br/>{}<br/>{}\"]\n",
+                i,
+                escape(&node.name),
+                escape(&node.version),
+                escape(&node.repo)
+            ));
+        }
+        for edge in &self.edges {
+            out.push_str(&format!("  {} --> {}\n", id(&edge.from), id(&edge.to)));
+        }
+        let direct: Vec<String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.direct)
+            .map(|(i, _)| format!("n{i}"))
+            .collect();
+        if !direct.is_empty() {
+            out.push_str("  classDef direct fill:#add8e6,stroke-width:2px\n");
+            out.push_str(&format!("  class {} direct\n", direct.join(",")));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::mk_package;
+
+    #[test]
+    fn it_renders_graphs() {
+        let app = mk_package("app", vec!["base", "missing"]);
+        let base = mk_package("base", vec![]);
+        let graph = DependencyGraph::new(&[&base, &app], &["app".to_string()]);
+        assert_eq!(
+            graph.edges,
+            vec![GraphEdge {
+                from: "app".to_string(),
+                to: "base".to_string()
+            }]
+        );
+        assert_eq!(
+            graph.to_dot(),
+            r#"digraph packages {
+  node [shape=box];
+  "app" [label="app\nv1.0.0\nhttps://github.com/example/app", style="bold,filled", fillcolor=lightblue];
+  "base" [label="base\nv1.0.0\nhttps://github.com/example/base"];
+  "app" -> "base";
+}
+"#
+        );
+        assert_eq!(
+            graph.to_mermaid(),
+            r#"graph TD
+  n0["app<br/>v1.0.0<br/>https://github.com/example/app"]
+  n1["base<br/>v1.0.0<br/>https://github.com/example/base"]
+  n0 --> n1
+  classDef direct fill:#add8e6,stroke-width:2px
+  class n0 direct
+"#
+        );
+    }
+}
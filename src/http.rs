@@ -0,0 +1,256 @@
+//! Downloads over HTTP(S) or from `file://` URLs, after applying the URL
+//! rewrites of the vessel configuration. Requests carry the credentials
+//! `auth` finds for their host, use the configured network settings and are
+//! retried with exponential backoff when they fail with a network error or
+//! a retryable status. Retries stop once the response arrived, a connection
+//! that breaks while the body is read fails the download. git commands
+//! aren't retried either.
+
+use crate::auth;
+use crate::config::{config, rewrite_url};
+use crate::VesselError;
+use anyhow::{Context, Result};
+use log::warn;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::HeaderValue;
+use reqwest::{Certificate, NoProxy, Proxy, StatusCode};
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::io::{self, Read};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+/// Downloads `url` and returns the response body as a stream
+pub(crate) fn get(url: &str) -> Result<Box<dyn Read + Send>> {
+    request(url, None)
+}
+
+/// Downloads `url` and returns the response body as text
+pub(crate) fn get_text(url: &str) -> Result<String> {
+    let mut text = String::new();
+    get(url)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// Fetches `url` from the GitHub API, or a stand-in for it, and parses the
+/// response
+pub(crate) fn get_json<T: DeserializeOwned>(url: &str) -> Result<T> {
+    let response = request(url, Some("application/vnd.github.v3+json"))?;
+    Ok(serde_json::from_reader(response)?)
+}
+
+/// The client shared by all requests, configured with the network settings
+fn client() -> Result<&'static Client> {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    if let Some(client) = CLIENT.get() {
+        return Ok(client);
+    }
+    let network = &config()?.network;
+    let mut builder = Client::builder()
+        .connect_timeout(network.connect_timeout())
+        .timeout(network.timeout());
+    if let Some(proxy) = &network.proxy {
+        let no_proxy = network.no_proxy.as_deref().and_then(NoProxy::from_string);
+        builder = builder.proxy(
+            Proxy::all(proxy)
+                .context("Invalid proxy in the vessel configuration")?
+                .no_proxy(no_proxy),
+        );
+    }
+    // The bundle replaces the built-in roots, like `http.sslCAInfo` does for git
+    if let Some(ca_bundle) = &network.ca_bundle {
+        let pem = fs::read(ca_bundle).context(format!(
+            "Failed to read the CA bundle {}",
+            ca_bundle.display()
+        ))?;
+        let certificates = Certificate::from_pem_bundle(&pem).context(format!(
+            "Failed to parse the CA bundle {}",
+            ca_bundle.display()
+        ))?;
+        builder = builder.tls_built_in_root_certs(false);
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    let client = builder.build()?;
+    Ok(CLIENT.get_or_init(|| client))
+}
+
+fn request(url: &str, accept: Option<&str>) -> Result<Box<dyn Read + Send>> {
+    let url = rewrite_url(url)?;
+    if let Some(path) = url.strip_prefix("file://") {
+        // Missing files are reported like missing pages, so callers can
+        // treat mirrors on disk like the servers they stand in for
+        let file = fs::File::open(path).map_err(|err| VesselError::DownloadFailed {
+            url: url.clone(),
+            status: (err.kind() == io::ErrorKind::NotFound).then_some(404),
+            details: err.to_string(),
+        })?;
+        return Ok(Box::new(file));
+    }
+    let retries = config()?.network.retries();
+    let credential = auth::credential_for(&url)?;
+    let mut attempt = 0;
+    loop {
+        let mut request = client()?
+            .get(&url)
+            .header(reqwest::header::USER_AGENT, "vessel");
+        if let Some(accept) = accept {
+            request = request.header(reqwest::header::ACCEPT, accept);
+        }
+        // reqwest drops the header when it follows a redirect to another host
+        if let Some(credential) = &credential {
+            let mut value = HeaderValue::from_str(&credential.header())?;
+            value.set_sensitive(true);
+            request = request.header(reqwest::header::AUTHORIZATION, value);
+        }
+        let response = request.send();
+        let failure = match &response {
+            Ok(response) if is_retryable(response.status()) => Some(response.status().to_string()),
+            Err(err) if err.is_timeout() || err.is_connect() => Some(err.to_string()),
+            _ => None,
+        };
+        match failure {
+            Some(failure) if attempt < retries => {
+                let delay = backoff(attempt, response.ok().as_ref().and_then(retry_after));
+                warn!(
+                    "Downloading {} failed ({}), retrying in {}s",
+                    url,
+                    failure,
+                    delay.as_secs()
+                );
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            _ => return Ok(Box::new(check_response(&url, response)?)),
+        }
+    }
+}
+
+/// Statuses that might go away when the request is retried
+fn is_retryable(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 408 | 429 | 500 | 502 | 503 | 504)
+}
+
+/// How long the server asked us to wait in the `Retry-After` header, if it
+/// gave a number of seconds
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// How long to wait before the retry after `attempt`, doubling from one
+/// second up to a minute
+fn backoff(attempt: u32, retry_after: Option<Duration>) -> Duration {
+    let delay = Duration::from_secs(1 << attempt.min(6));
+    retry_after
+        .map_or(delay, |retry_after| retry_after.max(delay))
+        .min(Duration::from_secs(60))
+}
+
+/// The environment variables that make git use the URL rewrites, the network
+/// settings and the credentials for `url`. git applies the rewrites itself,
+/// so they also apply to submodules. Passing the settings through the
+/// environment instead of the command line keeps the credentials out of
+/// process listings and error messages.
+pub(crate) fn git_env(url: &str) -> Result<Vec<(String, String)>> {
+    let config = config()?;
+    let network = &config.network;
+    let mut settings = auth::git_config(&rewrite_url(url)?)?;
+    for rule in config.rules() {
+        settings.push((format!("url.{}.insteadOf", rule.replacement), rule.prefix));
+    }
+    if let Some(proxy) = &network.proxy {
+        settings.push(("http.proxy".to_string(), proxy.clone()));
+    }
+    if let Some(ca_bundle) = &network.ca_bundle {
+        settings.push((
+            "http.sslCAInfo".to_string(),
+            ca_bundle.display().to_string(),
+        ));
+    }
+    // git has no read timeout, it aborts transfers that are stalled for this long
+    settings.push(("http.lowSpeedLimit".to_string(), "1".to_string()));
+    settings.push((
+        "http.lowSpeedTime".to_string(),
+        network.timeout().as_secs().to_string(),
+    ));
+
+    let mut env = vec![("GIT_CONFIG_COUNT".to_string(), settings.len().to_string())];
+    for (i, (key, value)) in settings.into_iter().enumerate() {
+        env.push((format!("GIT_CONFIG_KEY_{i}"), key));
+        env.push((format!("GIT_CONFIG_VALUE_{i}"), value));
+    }
+    if let Some(no_proxy) = &network.no_proxy {
+        env.push(("no_proxy".to_string(), no_proxy.clone()));
+    }
+    Ok(env)
+}
+
+/// Turns network errors and unsuccessful responses for `url` into
+/// `VesselError::DownloadFailed`
+fn check_response(url: &str, response: reqwest::Result<Response>) -> Result<Response> {
+    let response = response.map_err(|err| VesselError::DownloadFailed {
+        url: url.to_string(),
+        status: None,
+        details: err.to_string(),
+    })?;
+    if !response.status().is_success() {
+        return Err(VesselError::DownloadFailed {
+            url: url.to_string(),
+            status: Some(response.status().as_u16()),
+            details: response
+                .text()
+                .unwrap_or_else(|_| "No more details".to_string()),
+        }
+        .into());
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_reads_file_urls() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("package-set.dhall");
+        fs::write(&path, "[] : List Text").unwrap();
+        let url = format!("file://{}", path.display());
+        assert_eq!(get_text(&url).unwrap(), "[] : List Text");
+
+        let err = get_text(&format!("{url}.missing")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<VesselError>(),
+            Some(VesselError::DownloadFailed {
+                status: Some(404),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn it_backs_off_exponentially() {
+        assert_eq!(backoff(0, None), Duration::from_secs(1));
+        assert_eq!(backoff(3, None), Duration::from_secs(8));
+        assert_eq!(backoff(10, None), Duration::from_secs(60));
+        assert_eq!(
+            backoff(0, Some(Duration::from_secs(5))),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            backoff(0, Some(Duration::from_secs(600))),
+            Duration::from_secs(60)
+        );
+        assert!(is_retryable(StatusCode::BAD_GATEWAY));
+        assert!(!is_retryable(StatusCode::NOT_FOUND));
+    }
+}
@@ -1,27 +1,100 @@
 use anyhow::{self, Context, Result};
 use flate2::read::GzDecoder;
 use log::{debug, info, warn};
-use semver::Version;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::cfg;
+use serde_dhall::{SimpleType, SimpleValue, StaticType};
+use sha2::{Digest, Sha256};
 use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
-use std::io::Write;
+use std::io::{Read, Seek, Write};
 use std::iter::Iterator;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
 use tar::Archive;
 use tempfile::TempDir;
 use topological_sort::TopologicalSort;
 use walkdir::WalkDir;
 
+mod auth;
+mod config;
+mod diff;
+mod error;
+mod graph;
+mod http;
+mod lock;
+mod manifest;
+mod platform;
+mod progress;
+mod resolve;
+mod tree;
+
+pub use auth::HostCredentials;
+pub use config::{config, config_file, Config, Mirrors, Network, Rewrite};
+pub use diff::{changes_to_markdown, diff_package_sets, PackageChange};
+pub use error::VesselError;
+pub use graph::{DependencyGraph, GraphEdge, GraphNode};
+pub use lock::{LockedPackage, Lockfile, LOCK_FILE};
+pub use platform::{Arch, Os, Platform};
+pub use progress::{format_size, set_interactive, suspend_status};
+pub use resolve::{RequiredBy, Requirement};
+pub use tree::TreeOptions;
+
+use progress::Progress;
+
+/// How many packages are downloaded at the same time by default
+pub const DEFAULT_JOBS: usize = 8;
+
+/// Options that control how `Vessel::install_packages` installs packages
+#[derive(Debug, Clone)]
+pub struct InstallOptions {
+    /// Re-download packages even if they are installed already
+    pub force: bool,
+    /// Ignore the existing lock file and re-resolve every package
+    pub update_lock: bool,
+    /// The maximum number of packages to download concurrently
+    pub jobs: usize,
+}
+
+impl Default for InstallOptions {
+    fn default() -> Self {
+        InstallOptions {
+            force: false,
+            update_lock: false,
+            jobs: DEFAULT_JOBS,
+        }
+    }
+}
+
+/// Options that control what `Vessel::gc` removes
+#[derive(Debug, Clone, Default)]
+pub struct GcOptions {
+    /// Keep every package in the package set, not just the ones the manifest needs
+    pub keep_package_set: bool,
+    /// Only report what would be removed
+    pub dry_run: bool,
+}
+
 #[derive(Debug, Default)]
 pub struct Vessel {
     pub package_set: PackageSet,
     pub manifest: Manifest,
     /// How many parent directories are we nested underneath the project root
     pub nested: u32,
+    /// Only use packages and compilers that were downloaded already
+    pub offline: bool,
+    /// Download compilers for this platform instead of the host's
+    pub platform: Option<Platform>,
+    /// Install compilers that no checksum is known for instead of failing
+    pub allow_unverified_compiler: bool,
+    /// The version ranges of `name@range` dependencies in the manifest and
+    /// the package set
+    pub requirements: Vec<Requirement>,
 }
 
 impl Vessel {
@@ -65,22 +138,59 @@ impl Vessel {
         Ok(new_vessel)
     }
 
+    /// Like `new`, but only reads the manifest if there is one
+    pub fn new_with_optional_manifest(package_set_file: &Path) -> Result<Vessel> {
+        let nested = Vessel::find_dominating_manifest()?;
+        let mut new_vessel = Vessel {
+            nested: nested.unwrap_or(0),
+            ..Default::default()
+        };
+        new_vessel.read_package_set(package_set_file)?;
+        if nested.is_some() {
+            new_vessel.read_manifest_file()?;
+        }
+        Ok(new_vessel)
+    }
+
     fn read_manifest_file(&mut self) -> Result<()> {
         let manifest_file = PathBuf::from("vessel.dhall");
-        self.manifest = serde_dhall::from_file(manifest_file)
-            .static_type_annotation()
-            .parse()
-            .context("Failed to parse the vessel.dhall file")?;
+        let manifest = parse_typed(DhallSource::File(&manifest_file), |source| {
+            VesselError::ManifestParse {
+                path: manifest_file.clone(),
+                source,
+            }
+        })?;
+        self.set_manifest(manifest);
         Ok(())
     }
 
+    /// Replaces the manifest, moving the ranges of its `name@range`
+    /// dependencies into `requirements`
+    fn set_manifest(&mut self, mut manifest: Manifest) {
+        self.requirements
+            .retain(|requirement| requirement.required_by != RequiredBy::Manifest);
+        self.requirements.extend(resolve::split_dependencies(
+            &mut manifest.dependencies,
+            RequiredBy::Manifest,
+        ));
+        self.manifest = manifest;
+    }
+
     fn read_package_set(&mut self, package_set_file: &Path) -> Result<()> {
-        self.package_set = PackageSet::new(
-            serde_dhall::from_file(package_set_file)
-                .static_type_annotation()
-                .parse()
-                .context("Failed to parse the package set file")?,
-        );
+        let mut packages: Vec<Package> =
+            parse_typed(DhallSource::File(package_set_file), |source| {
+                VesselError::PackageSetParse {
+                    path: package_set_file.to_path_buf(),
+                    source,
+                }
+            })?;
+        for package in &mut packages {
+            self.requirements.extend(resolve::split_dependencies(
+                &mut package.dependencies,
+                RequiredBy::Package(package.name.clone()),
+            ));
+        }
+        self.package_set = PackageSet::new(packages);
         Ok(())
     }
 
@@ -96,25 +206,367 @@ impl Vessel {
         res.join(path)
     }
 
-    /// Installs all transitive dependencies and returns a mapping of package name -> installation location
-    pub fn install_packages(&self, force: bool) -> Result<Vec<(Name, PathBuf)>> {
-        let install_plan = self
-            .package_set
-            .transitive_deps(self.manifest.dependencies.clone());
+    /// Installs all transitive dependencies and returns where they were installed
+    ///
+    /// Packages listed in the lock file are installed at their locked commit
+    /// and have to match the locked hash. The lock file is updated afterwards.
+    pub fn install_packages(&self, opts: &InstallOptions) -> Result<Vec<InstalledPackage>> {
+        let lock_path = Path::new(LOCK_FILE);
+        let lockfile = if opts.update_lock {
+            Lockfile::default()
+        } else {
+            Lockfile::read(lock_path)?.unwrap_or_default()
+        };
+        let package_set = self
+            .resolved_package_set(Some(self.manifest.dependencies.clone()), &lockfile)
+            .context("Failed to resolve the dependencies in vessel.dhall")?;
+        let install_plan = package_set
+            .transitive_deps(self.manifest.dependencies.clone())
+            .context("Failed to resolve the dependencies in vessel.dhall")?;
+
+        for package in &install_plan {
+            if let Some(locked) = lockfile.find(&package.name) {
+                locked.check_matches(package)?;
+            }
+        }
 
         info!("Installing {} packages", install_plan.len());
 
-        let paths = install_plan
+        if opts.force && self.offline {
+            return Err(anyhow::anyhow!(
+                "Can't force a reinstall of all packages in offline mode"
+            ));
+        }
+
+        let progress = Progress::new("installed", install_plan.len());
+        let results = run_parallel(&install_plan, opts.jobs, |package| {
+            let result = install_package(
+                package,
+                opts.force,
+                lockfile.find(&package.name),
+                self.offline,
+                Some(&progress),
+            );
+            progress.finish(&package.name);
+            result
+        });
+        drop(progress);
+        let mut locked = vec![];
+        let mut errors: Vec<(Name, anyhow::Error)> = vec![];
+        for (package, result) in install_plan.iter().zip(results) {
+            match result {
+                Ok(l) => locked.push(l),
+                Err(err) => errors.push((package.name.clone(), err)),
+            }
+        }
+        if !errors.is_empty() {
+            let err = anyhow::Error::new(VesselError::PackagesFailed {
+                action: "install".to_string(),
+                errors,
+            });
+            return Err(if self.offline {
+                err.context("Vessel is running in offline mode, run `vessel install` with network access first")
+            } else {
+                err
+            });
+        }
+        Lockfile::new(locked).write(lock_path)?;
+
+        info!("Installation complete.");
+
+        install_plan
             .iter()
             .map(|package| {
-                download_package(package, force)
-                    .map(|path| (package.name.clone(), self.nested_path(path)))
+                Ok(InstalledPackage {
+                    name: package.name.clone(),
+                    version: package.version.clone(),
+                    repo: package.repo.clone(),
+                    path: self.nested_path(package.install_path()?),
+                    direct: self.manifest.dependencies.contains(&package.name),
+                })
             })
-            .collect::<Result<Vec<(String, PathBuf)>>>()?;
+            .collect()
+    }
 
-        info!("Installation complete.");
+    /// Replaces the versions of packages that need to satisfy a version range
+    /// with the highest matching tag of their repo. Only packages needed by
+    /// `entry_points` are resolved, or all packages if there are none.
+    ///
+    /// Exact versions in the package set are kept if they satisfy all ranges,
+    /// otherwise versions from `lockfile` are preferred over looking up tags.
+    pub fn resolve_versions(
+        &mut self,
+        entry_points: Option<Vec<Name>>,
+        lockfile: &Lockfile,
+    ) -> Result<()> {
+        self.package_set = self.resolved_package_set(entry_points, lockfile)?;
+        Ok(())
+    }
 
-        Ok(paths)
+    /// Like `resolve_versions`, but returns the resolved package set and
+    /// leaves this one alone
+    pub fn resolved_package_set(
+        &self,
+        entry_points: Option<Vec<Name>>,
+        lockfile: &Lockfile,
+    ) -> Result<PackageSet> {
+        self.resolve_with_tags(entry_points, lockfile, &mut HashMap::new())
+    }
+
+    /// Like `resolved_package_set`, but looks up the tags of a repo in `tags`
+    /// first and adds the ones it had to list, so callers can reuse them
+    fn resolve_with_tags(
+        &self,
+        entry_points: Option<Vec<Name>>,
+        lockfile: &Lockfile,
+        tags: &mut HashMap<Url, Vec<Tag>>,
+    ) -> Result<PackageSet> {
+        let scope: Vec<&Package> = match entry_points {
+            Some(entry_points) => self.package_set.transitive_deps(entry_points)?,
+            None => self.package_set.0.values().collect(),
+        };
+        let requirements = self.requirements_in_scope(&scope);
+        let requirements: Vec<&Requirement> = requirements.iter().collect();
+
+        let mut resolved: Vec<(Name, Tag)> = vec![];
+        for chunk in requirements.chunk_by(|a, b| a.package == b.package) {
+            let package = self.package_set.find_dependency(&chunk[0].package, None)?;
+            if !resolve::is_range(&package.version) {
+                if resolve::parse_tag(&package.version).is_none() {
+                    warn!(
+                        "Can't check the version ranges of \"{}\", as \"{}\" isn't a semver tag",
+                        package.name, package.version
+                    );
+                    continue;
+                }
+                if resolve::satisfies(&package.version, chunk)? {
+                    continue;
+                }
+            }
+            let locked = match lockfile.find(&package.name) {
+                Some(locked) if locked.repo == package.repo => Some(locked.version.clone())
+                    .filter(|version| resolve::satisfies(version, chunk).unwrap_or(false)),
+                _ => None,
+            };
+            let version = match locked {
+                Some(version) => version,
+                None if self.offline => {
+                    return Err(VesselError::NotAvailableOffline {
+                        name: package.name.clone(),
+                        version: package.version.clone(),
+                    }
+                    .into())
+                }
+                None => {
+                    if !tags.contains_key(&package.repo) {
+                        tags.insert(package.repo.clone(), resolve::list_tags(&package.repo)?);
+                    }
+                    let version =
+                        resolve::pick_version(&package.name, chunk, &tags[&package.repo])?;
+                    info!("Resolved \"{}\" to version {}", package.name, version);
+                    version
+                }
+            };
+            resolved.push((package.name.clone(), version));
+        }
+        let mut package_set = self.package_set.clone();
+        for (name, version) in resolved {
+            if let Some(package) = package_set.0.get_mut(&name) {
+                package.version = version;
+            }
+        }
+        Ok(package_set)
+    }
+
+    /// Collects the version ranges that apply to the packages in `scope`,
+    /// sorted by package name
+    fn requirements_in_scope(&self, scope: &[&Package]) -> Vec<Requirement> {
+        let in_scope = |name: &str| scope.iter().any(|package| package.name == name);
+        let mut requirements: Vec<Requirement> = scope
+            .iter()
+            .filter(|package| resolve::is_range(&package.version))
+            .map(|package| Requirement {
+                package: package.name.clone(),
+                range: package.version.clone(),
+                required_by: RequiredBy::PackageSet,
+            })
+            .chain(
+                self.requirements
+                    .iter()
+                    .filter(|requirement| {
+                        in_scope(&requirement.package)
+                            && match &requirement.required_by {
+                                RequiredBy::Package(name) => in_scope(name),
+                                _ => true,
+                            }
+                    })
+                    .cloned(),
+            )
+            .collect();
+        requirements.sort_by(|a, b| a.package.cmp(&b.package));
+        requirements
+    }
+
+    /// Looks up the tags of every package the manifest depends on and compares
+    /// them to the installed versions
+    pub fn outdated(&self, jobs: usize) -> Result<Vec<OutdatedPackage>> {
+        if self.offline {
+            return Err(anyhow::anyhow!(
+                "Can't look up newer versions in offline mode"
+            ));
+        }
+        let requirements = {
+            let scope = self
+                .package_set
+                .transitive_deps(self.manifest.dependencies.clone())
+                .context("Failed to resolve the dependencies in vessel.dhall")?;
+            self.requirements_in_scope(&scope)
+        };
+        let lockfile = Lockfile::read(Path::new(LOCK_FILE))?.unwrap_or_default();
+        let mut tags = HashMap::new();
+        let package_set = self
+            .resolve_with_tags(
+                Some(self.manifest.dependencies.clone()),
+                &lockfile,
+                &mut tags,
+            )
+            .context("Failed to resolve the dependencies in vessel.dhall")?;
+        let plan = package_set.transitive_deps(self.manifest.dependencies.clone())?;
+
+        // Repos whose tags were listed to resolve a range aren't listed again
+        let results = run_parallel(&plan, jobs, |package| match tags.get(&package.repo) {
+            Some(tags) => Ok(tags.clone()),
+            None => resolve::list_tags(&package.repo),
+        });
+        let mut outdated = vec![];
+        let mut errors: Vec<(Name, anyhow::Error)> = vec![];
+        for (package, tags) in plan.iter().zip(results) {
+            let tags = match tags {
+                Ok(tags) => tags,
+                Err(err) => {
+                    errors.push((package.name.clone(), err));
+                    continue;
+                }
+            };
+            let any_version = Requirement {
+                package: package.name.clone(),
+                range: "*".to_string(),
+                required_by: RequiredBy::PackageSet,
+            };
+            let mut compatible: Vec<Requirement> = requirements
+                .iter()
+                .filter(|requirement| requirement.package == package.name)
+                .cloned()
+                .collect();
+            let compatible = match resolve::parse_tag(&package.version) {
+                None => None,
+                Some(current) => {
+                    compatible.push(Requirement {
+                        range: format!("^{current}"),
+                        ..any_version.clone()
+                    });
+                    resolve::highest_matching(&tags, &compatible.iter().collect::<Vec<_>>())?
+                }
+            };
+            outdated.push(OutdatedPackage {
+                name: package.name.clone(),
+                current: package.version.clone(),
+                compatible,
+                latest: resolve::highest_matching(&tags, &[&any_version])?,
+            });
+        }
+        if !errors.is_empty() {
+            return Err(VesselError::PackagesFailed {
+                action: "look up the tags of".to_string(),
+                errors,
+            }
+            .into());
+        }
+        Ok(outdated)
+    }
+
+    /// Removes package versions, compilers and temporary directories from
+    /// `.vessel` that aren't needed by the manifest anymore. Compilers are
+    /// kept for a while after they were last used and temporary directories
+    /// while they are still being written to, as other vessel processes might
+    /// need them.
+    pub fn gc(&self, opts: &GcOptions) -> Result<GcReport> {
+        let lockfile = Lockfile::read(Path::new(LOCK_FILE))?.unwrap_or_default();
+        let entry_points = if opts.keep_package_set {
+            None
+        } else {
+            Some(self.manifest.dependencies.clone())
+        };
+        let package_set = self
+            .resolved_package_set(entry_points.clone(), &lockfile)
+            .context("Failed to resolve the dependencies in vessel.dhall")?;
+        let live: Vec<&Package> = match entry_points {
+            Some(entry_points) => package_set.transitive_deps(entry_points)?,
+            None => package_set.0.values().collect(),
+        };
+        let live: HashSet<(&str, &str)> = live
+            .iter()
+            .map(|package| (package.name.as_str(), package.version.as_str()))
+            .collect();
+
+        let vessel_dir = Path::new(".vessel");
+        let mut report = GcReport::default();
+        if !vessel_dir.exists() {
+            return Ok(report);
+        }
+        let mut garbage = vec![];
+        for dir in sorted_entries(vessel_dir)? {
+            let name = dir.file_name().unwrap_or_default().to_string_lossy();
+            if name == ".tmp" {
+                // Leftovers of interrupted downloads
+                garbage.extend(
+                    sorted_entries(&dir)?
+                        .into_iter()
+                        .filter(|entry| unused_for(&last_modified(entry), TMP_GRACE_PERIOD)),
+                );
+            } else if name == ".bin" {
+                for version in sorted_entries(&dir)? {
+                    if version.file_name().map(|v| v.to_string_lossy()).as_deref()
+                        == self.manifest.compiler.as_deref()
+                    {
+                        continue;
+                    }
+                    let installs = if is_unkeyed_compiler(&version) {
+                        vec![version]
+                    } else {
+                        sorted_entries(&version)?
+                    };
+                    garbage.extend(installs.into_iter().filter(|install| {
+                        unused_for(&compiler_last_used(install), COMPILER_GRACE_PERIOD)
+                    }));
+                }
+            } else if dir.is_dir() {
+                for version in sorted_entries(&dir)? {
+                    let version_name = version.file_name().unwrap_or_default().to_string_lossy();
+                    if !live.contains(&(name.as_ref(), version_name.as_ref())) {
+                        garbage.push(version);
+                    }
+                }
+            }
+        }
+
+        for path in garbage {
+            report.reclaimed += disk_usage(&path);
+            if !opts.dry_run {
+                if path.is_dir() {
+                    fs::remove_dir_all(&path)
+                } else {
+                    fs::remove_file(&path)
+                }
+                .context(format!("Failed to remove {}", path.display()))?;
+                // Removes the package or compiler version directory with its last entry
+                if let Some(parent) = path.parent() {
+                    let _ = fs::remove_dir(parent);
+                }
+            }
+            report.removed.push(path);
+        }
+        Ok(report)
     }
 
     /// Downloads the compiler binaries at the version specified in the manifest
@@ -124,32 +576,206 @@ impl Vessel {
             self.manifest.compiler.as_ref().ok_or_else(|| {
                 anyhow::anyhow!("No compiler version was specified in vessel.dhall")
             })?;
-        download_compiler(version).map(|path| self.nested_path(path))
+        download_compiler(
+            version,
+            self.manifest.compiler_sha256.as_deref(),
+            self.platform,
+            self.offline,
+            self.allow_unverified_compiler,
+        )
+        .map(|path| self.nested_path(path))
+    }
+
+    /// Renders the dependency tree of the manifest's dependencies, or of
+    /// `opts.package`. Inverted trees show which installed packages depend on
+    /// a package, starting from the packages without dependencies. Version
+    /// ranges are resolved the same way `install_packages` resolves them.
+    pub fn dependency_tree(&self, opts: &TreeOptions) -> Result<String> {
+        let mut entry_points = self.manifest.dependencies.clone();
+        entry_points.extend(opts.package.clone());
+        let lockfile = Lockfile::read(Path::new(LOCK_FILE))?.unwrap_or_default();
+        let package_set = self
+            .resolved_package_set(Some(entry_points), &lockfile)
+            .context("Failed to resolve the dependencies in vessel.dhall")?;
+        let install_plan = package_set
+            .transitive_deps(self.manifest.dependencies.clone())
+            .context("Failed to resolve the dependencies in vessel.dhall")?;
+        let roots: Vec<&Package> = match &opts.package {
+            Some(name) => {
+                // Makes sure the whole tree below the package can be resolved
+                package_set.transitive_deps(vec![name.clone()])?;
+                vec![package_set.find_dependency(name, None)?]
+            }
+            None if opts.invert => install_plan
+                .iter()
+                .filter(|package| package.dependencies.is_empty())
+                .copied()
+                .collect(),
+            None => install_plan
+                .iter()
+                .filter(|package| self.manifest.dependencies.contains(&package.name))
+                .copied()
+                .collect(),
+        };
+        Ok(if opts.invert {
+            tree::render(
+                &roots,
+                |package| {
+                    install_plan
+                        .iter()
+                        .filter(|dependent| dependent.dependencies.contains(&package.name))
+                        .copied()
+                        .collect()
+                },
+                opts.depth,
+            )
+        } else {
+            tree::render(
+                &roots,
+                |package| {
+                    package
+                        .dependencies
+                        .iter()
+                        .filter_map(|dep| package_set.find(dep))
+                        .collect()
+                },
+                opts.depth,
+            )
+        })
+    }
+
+    /// Returns the dependency graph of the whole package set, or only of the
+    /// manifest's transitive dependencies if `manifest_only` is set, with the
+    /// versions that version ranges resolve to
+    pub fn dependency_graph(&self, manifest_only: bool) -> Result<DependencyGraph> {
+        let entry_points = Some(self.manifest.dependencies.clone()).filter(|_| manifest_only);
+        let lockfile = Lockfile::read(Path::new(LOCK_FILE))?.unwrap_or_default();
+        let package_set = self
+            .resolved_package_set(entry_points, &lockfile)
+            .context("Failed to resolve the versions of the package set")?;
+        let packages = if manifest_only {
+            package_set
+                .transitive_deps(self.manifest.dependencies.clone())
+                .context("Failed to resolve the dependencies in vessel.dhall")?
+        } else {
+            package_set.topo_sorted()?
+        };
+        Ok(DependencyGraph::new(&packages, &self.manifest.dependencies))
+    }
+
+    /// Explains why a package is installed by listing every dependency path
+    /// from the manifest's dependencies to it
+    pub fn why(&self, name: &str) -> Result<Vec<Vec<&str>>> {
+        self.package_set.find_dependency(name, None)?;
+        self.package_set
+            .dependency_paths(&self.manifest.dependencies, name)
+            .context("Failed to resolve the dependencies in vessel.dhall")
+    }
+
+    /// Adds packages from the package set to the dependencies in vessel.dhall.
+    /// Returns the names that weren't dependencies already.
+    pub fn add_dependencies(&mut self, names: &[Name]) -> Result<Vec<Name>> {
+        let mut added: Vec<Name> = vec![];
+        for dependency in names {
+            let (name, range) = resolve::split_requirement(dependency);
+            let package = self.package_set.find_dependency(name, None)?;
+            if let Some(range) = range {
+                semver::VersionReq::parse(range).map_err(|_| VesselError::InvalidVersion {
+                    version: range.to_string(),
+                })?;
+            }
+            if self.manifest.dependencies.contains(&package.name)
+                || added
+                    .iter()
+                    .any(|a| resolve::split_requirement(a).0 == package.name)
+            {
+                info!("\"{}\" is already a dependency", package.name);
+            } else {
+                added.push(dependency.clone());
+            }
+        }
+        self.edit_manifest(|source| manifest::add_dependencies(source, &added))?;
+        Ok(added)
+    }
+
+    /// Removes packages from the dependencies in vessel.dhall and warns about
+    /// the ones that are still needed by the remaining dependencies. Returns
+    /// the names that were removed.
+    pub fn remove_dependencies(&mut self, names: &[Name]) -> Result<Vec<Name>> {
+        let mut removed: Vec<Name> = vec![];
+        for name in names {
+            if !self.manifest.dependencies.contains(name) {
+                warn!("\"{}\" is not a dependency in vessel.dhall", name);
+            } else if !removed.contains(name) {
+                removed.push(name.clone());
+            }
+        }
+        self.edit_manifest(|source| manifest::remove_dependencies(source, &removed))?;
+        for name in &removed {
+            let dependents: Vec<&str> = self
+                .manifest
+                .dependencies
+                .iter()
+                .filter(|dep| {
+                    self.package_set
+                        .transitive_deps(vec![dep.to_string()])
+                        .is_ok_and(|deps| deps.iter().any(|p| &p.name == name))
+                })
+                .map(String::as_str)
+                .collect();
+            if !dependents.is_empty() {
+                warn!(
+                    "\"{}\" is still installed, because it is a dependency of {:?}",
+                    name, dependents
+                );
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Rewrites vessel.dhall with `edit` and reloads the manifest
+    fn edit_manifest(&mut self, edit: impl FnOnce(&str) -> Result<String>) -> Result<()> {
+        let manifest_file = Path::new("vessel.dhall");
+        let source = fs::read_to_string(manifest_file).context("Failed to read vessel.dhall")?;
+        let edited = edit(&source)?;
+        if edited == source {
+            return Ok(());
+        }
+        let manifest = parse_typed(DhallSource::Text(&edited), anyhow::Error::from)
+            .context("Failed to edit vessel.dhall, please edit the file by hand")?;
+        self.set_manifest(manifest);
+        fs::write(manifest_file, edited).context("Failed to write vessel.dhall")
     }
 
     /// Verifies that every source file inside the given package compiles in the current package set
     pub fn verify_package(&self, moc: &Path, moc_args: &Option<String>, name: &str) -> Result<()> {
+        self.check_package(moc, moc_args, name).map(|_| ())
+    }
+
+    /// Like `verify_package`, but returns the compiler's warnings
+    fn check_package(&self, moc: &Path, moc_args: &Option<String>, name: &str) -> Result<String> {
         match self.package_set.find(name) {
-            None => Err(anyhow::anyhow!(
-                "The package \"{}\" does not exist in the package set",
-                name
-            )),
+            None => Err(self.package_set.unknown_package(name, None)),
             Some(package) => {
                 let mut cmd = Command::new(moc);
                 cmd.arg("--check");
                 if let Some(args) = moc_args {
                     cmd.args(args.split(' '));
                 }
-                download_package(package, false)?;
+                download_package(package, false, self.offline)?;
                 let dependencies = self
                     .package_set
-                    .transitive_deps(package.dependencies.clone());
+                    .transitive_deps(package.dependencies.clone())
+                    .context(format!(
+                        "Failed to resolve the dependencies of \"{}\"",
+                        package.name
+                    ))?;
                 for package in dependencies {
-                    let path = download_package(package, false)?;
+                    let path = download_package(package, false, self.offline)?;
                     cmd.arg("--package").arg(&package.name).arg(path);
                 }
 
-                package.sources().for_each(|entry_point| {
+                package.sources()?.for_each(|entry_point| {
                     cmd.arg(entry_point);
                 });
                 let output = cmd.output().context(format!("Failed to run {cmd:?}"))?;
@@ -160,48 +786,95 @@ impl Vessel {
                     } else {
                         info!("Verified \"{}\"", package.name);
                     }
-                    Ok(())
+                    Ok(warnings)
                 } else {
-                    Err(anyhow::anyhow!(
-                        "Failed to verify \"{}\" with:\n{}",
-                        package.name,
-                        String::from_utf8(output.stderr)?
-                    ))
+                    Err(VesselError::VerificationFailed {
+                        package: package.name.clone(),
+                        stderr: String::from_utf8(output.stderr)?,
+                    }
+                    .into())
                 }
             }
         }
     }
 
     pub fn verify_all(&self, moc: &Path, moc_args: &Option<String>) -> Result<()> {
-        let mut errors: Vec<(Name, anyhow::Error)> = vec![];
-        for package in &self.package_set.topo_sorted() {
-            if !errors.iter().any(|(n, _)| package.dependencies.contains(n)) {
-                if let Err(err) = self.verify_package(moc, moc_args, &package.name) {
-                    errors.push((package.name.clone(), err))
+        VerificationReport::into_result("verify", self.verify_all_reports(moc, moc_args, false)?)
+    }
+
+    /// Verifies, or with `compile` compiles, every package in the package set
+    /// and reports the outcome for each of them. Packages depending on a
+    /// package that failed are skipped.
+    pub fn verify_all_reports(
+        &self,
+        moc: &Path,
+        moc_args: &Option<String>,
+        compile: bool,
+    ) -> Result<Vec<VerificationReport>> {
+        let packages = self.package_set.topo_sorted()?;
+        let progress = Progress::new(
+            if compile { "compiled" } else { "verified" },
+            packages.len(),
+        );
+        let mut reports: Vec<VerificationReport> = vec![];
+        for package in &packages {
+            let dependency_failed = reports.iter().any(|report| {
+                report.status == VerificationStatus::Failed
+                    && package.dependencies.contains(&report.name)
+            });
+            reports.push(if dependency_failed {
+                VerificationReport {
+                    name: package.name.clone(),
+                    status: VerificationStatus::Skipped,
+                    stderr: String::new(),
+                    error: None,
                 }
-            }
+            } else {
+                self.verify_package_report(moc, moc_args, &package.name, compile)
+            });
+            progress.finish(&package.name);
         }
-        if errors.is_empty() {
-            Ok(())
+        Ok(reports)
+    }
+
+    /// Verifies, or with `compile` compiles, a single package and reports the outcome
+    pub fn verify_package_report(
+        &self,
+        moc: &Path,
+        moc_args: &Option<String>,
+        name: &str,
+        compile: bool,
+    ) -> VerificationReport {
+        let result = if compile {
+            self.compile_package(moc, moc_args, name)
+                .map(|()| String::new())
         } else {
-            let err = anyhow::anyhow!(
-                "Failed to verify: {:?}",
-                errors.iter().map(|(ref n, _)| n).collect::<Vec<_>>()
-            );
-            for err in errors.iter().rev() {
-                eprintln!("{}", err.1);
-            }
-            Err(err)
+            self.check_package(moc, moc_args, name)
+        };
+        match result {
+            Ok(stderr) => VerificationReport {
+                name: name.to_string(),
+                status: VerificationStatus::Verified,
+                stderr,
+                error: None,
+            },
+            Err(err) => VerificationReport {
+                name: name.to_string(),
+                status: VerificationStatus::Failed,
+                stderr: match err.downcast_ref::<VesselError>() {
+                    Some(VesselError::VerificationFailed { stderr, .. })
+                    | Some(VesselError::CompilerFailed { stderr, .. }) => stderr.clone(),
+                    _ => format!("{err:#}"),
+                },
+                error: Some(err),
+            },
         }
     }
 
     /// Compiles every source file inside the given package in the current package set
     pub fn compile_package(&self, moc: &Path, moc_args: &Option<String>, name: &str) -> Result<()> {
         match self.package_set.find(name) {
-            None => Err(anyhow::anyhow!(
-                "The package \"{}\" does not exist in the package set",
-                name
-            )),
+            None => Err(self.package_set.unknown_package(name, None)),
             Some(package) => {
                 // Create temporary directory for WASM output files
                 let tmp = Path::new(".vessel").join(".tmp");
@@ -210,13 +883,17 @@ impl Vessel {
                 }
                 let temp_dir: TempDir = tempfile::tempdir_in(tmp)?;
 
-                download_package(package, false)?;
+                download_package(package, false, self.offline)?;
                 let dependencies = self
                     .package_set
-                    .transitive_deps(package.dependencies.clone());
+                    .transitive_deps(package.dependencies.clone())
+                    .context(format!(
+                        "Failed to resolve the dependencies of \"{}\"",
+                        package.name
+                    ))?;
 
                 // Compile each entry point separately
-                for (idx, entry_point) in package.sources().enumerate() {
+                for (idx, entry_point) in package.sources()?.enumerate() {
                     let mut single_cmd = Command::new(moc);
                     if let Some(args) = moc_args {
                         single_cmd.args(args.split(' '));
@@ -227,7 +904,7 @@ impl Vessel {
 
                     // Add package dependencies
                     for dep_package in &dependencies {
-                        let path = download_package(dep_package, false)?;
+                        let path = download_package(dep_package, false, self.offline)?;
                         single_cmd.arg("--package").arg(&dep_package.name).arg(path);
                     }
 
@@ -239,11 +916,11 @@ impl Vessel {
                         .context(format!("Failed to run {single_cmd:?}"))?;
 
                     if !output.status.success() {
-                        return Err(anyhow::anyhow!(
-                            "Failed to verify \"{}\" with:\n{}",
-                            package.name,
-                            String::from_utf8(output.stderr)?
-                        ));
+                        return Err(VesselError::CompilerFailed {
+                            package: package.name.clone(),
+                            stderr: String::from_utf8(output.stderr)?,
+                        }
+                        .into());
                     }
 
                     // Validate generated WASM file
@@ -254,11 +931,11 @@ impl Vessel {
                             .context("Failed to run wasm-validate - make sure it's installed")?;
 
                         if !validate_cmd.status.success() {
-                            return Err(anyhow::anyhow!(
-                                "WASM validation failed for \"{}\" with:\n{}",
-                                package.name,
-                                String::from_utf8(validate_cmd.stderr)?
-                            ));
+                            return Err(anyhow::Error::new(VesselError::CompilerFailed {
+                                package: package.name.clone(),
+                                stderr: String::from_utf8(validate_cmd.stderr)?,
+                            })
+                            .context("WASM validation failed"));
                         }
                     }
                 }
@@ -270,29 +947,167 @@ impl Vessel {
     }
 
     pub fn compile_all(&self, moc: &Path, moc_args: &Option<String>) -> Result<()> {
-        let mut errors: Vec<(Name, anyhow::Error)> = vec![];
-        for package in &self.package_set.topo_sorted() {
-            if !errors.iter().any(|(n, _)| package.dependencies.contains(n)) {
-                if let Err(err) = self.compile_package(moc, moc_args, &package.name) {
-                    errors.push((package.name.clone(), err))
-                }
-            }
-        }
+        VerificationReport::into_result("compile", self.verify_all_reports(moc, moc_args, true)?)
+    }
+}
+
+/// A package that was installed by `Vessel::install_packages`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstalledPackage {
+    pub name: Name,
+    pub version: Tag,
+    pub repo: Url,
+    pub path: PathBuf,
+    /// Whether the manifest depends on this package directly
+    pub direct: bool,
+}
+
+/// What `Vessel::gc` removed, or would remove in a dry run
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct GcReport {
+    pub removed: Vec<PathBuf>,
+    /// The number of bytes freed
+    pub reclaimed: u64,
+}
+
+/// The newest versions of a package, as reported by `Vessel::outdated`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OutdatedPackage {
+    pub name: Name,
+    /// The version that is installed
+    pub current: Tag,
+    /// The newest tag that is semver compatible with `current` and satisfies
+    /// all version ranges. `None` if `current` isn't a semver tag.
+    pub compatible: Option<Tag>,
+    /// The newest tag of the repo, not counting pre-releases
+    pub latest: Option<Tag>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerificationStatus {
+    Verified,
+    Failed,
+    /// Not verified, because one of the package's dependencies failed
+    Skipped,
+}
+
+/// The outcome of verifying or compiling a single package
+#[derive(Debug, Serialize)]
+pub struct VerificationReport {
+    pub name: Name,
+    pub status: VerificationStatus,
+    /// The compiler's output, warnings for verified packages and errors for failed ones
+    pub stderr: String,
+    #[serde(skip)]
+    pub error: Option<anyhow::Error>,
+}
+
+impl VerificationReport {
+    /// Fails with the errors of all failed packages, `action` describes what was attempted
+    pub fn into_result(action: &str, reports: Vec<VerificationReport>) -> Result<()> {
+        let errors: Vec<(Name, anyhow::Error)> = reports
+            .into_iter()
+            .filter_map(|VerificationReport { name, error, .. }| error.map(|err| (name, err)))
+            .collect();
         if errors.is_empty() {
             Ok(())
         } else {
-            let err = anyhow::anyhow!(
-                "Failed to compile: {:?}",
-                errors.iter().map(|(ref n, _)| n).collect::<Vec<_>>()
-            );
-            for err in errors.iter().rev() {
-                eprintln!("{}", err.1);
+            Err(VesselError::PackagesFailed {
+                action: action.to_string(),
+                errors,
             }
-            Err(err)
+            .into())
         }
     }
 }
 
+/// Runs `f` for every item on up to `jobs` threads and returns the results in
+/// the order of `items`
+fn run_parallel<T, R, F>(items: &[T], jobs: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::SeqCst);
+                let Some(item) = items.get(idx) else {
+                    break;
+                };
+                let result = f(item);
+                results.lock().unwrap()[idx] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("every item was processed"))
+        .collect()
+}
+
+/// Lists the entries of a directory in sorted order
+fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(dir)
+        .context(format!("Failed to read {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+/// Temporary directories that were written to more recently than this might
+/// belong to an install that is still running
+const TMP_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// Compilers other than the manifest's that were used more recently than this
+/// are kept, like the ones `verify --version` downloads
+const COMPILER_GRACE_PERIOD: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The file inside an installed compiler's directory whose modification time
+/// records when vessel last used it
+const LAST_USED_FILE: &str = ".vessel-last-used";
+
+/// The latest modification time of `path` and everything below it
+fn last_modified(path: &Path) -> Option<SystemTime> {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok()?.metadata().ok()?.modified().ok())
+        .max()
+}
+
+/// When the compiler in `dir` was last used, or installed if it was installed
+/// before vessel recorded its use
+fn compiler_last_used(dir: &Path) -> Option<SystemTime> {
+    fs::metadata(dir.join(LAST_USED_FILE))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .or_else(|| last_modified(dir))
+}
+
+/// Whether `time` lies more than `period` in the past. Unknown times count as
+/// recent, so nothing is removed by accident.
+fn unused_for(time: &Option<SystemTime>, period: Duration) -> bool {
+    time.and_then(|time| time.elapsed().ok())
+        .is_some_and(|elapsed| elapsed > period)
+}
+
+/// Sums up the size of all files below `path`
+fn disk_usage(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok()?.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
 /// Guards against path strings in package data
 fn is_valid_dirname(input: &str) -> bool {
     input
@@ -303,152 +1118,658 @@ fn is_valid_dirname(input: &str) -> bool {
 }
 
 /// Checks package name string
-fn validate_name(name: &str) -> &str {
-    assert!(is_valid_dirname(name), "Invalid package name: `{}`", name);
-    name
+fn validate_name(name: &str) -> Result<&str> {
+    if !is_valid_dirname(name) {
+        return Err(VesselError::InvalidName {
+            name: name.to_string(),
+        }
+        .into());
+    }
+    Ok(name)
 }
 
 /// Checks package or compiler version string
-fn validate_version(version: &str) -> &str {
-    assert!(
-        is_valid_dirname(version),
-        "Invalid version string: `{}`",
-        version
-    );
-    version
+fn validate_version(version: &str) -> Result<&str> {
+    if !is_valid_dirname(version) {
+        return Err(VesselError::InvalidVersion {
+            version: version.to_string(),
+        }
+        .into());
+    }
+    Ok(version)
+}
+
+/// Computes the edit distance between two strings, counting insertions,
+/// deletions, substitutions and transpositions of adjacent characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
 }
 
-pub fn download_compiler(version: &str) -> Result<PathBuf> {
-    let bin = Path::new(".vessel").join(".bin");
-    let dest = bin.join(validate_version(version));
+/// Downloads the compiler binaries at `version` for `platform`, or the host
+/// platform, into `.vessel/.bin/<version>/<platform>` and returns the path to
+/// them.
+///
+/// The downloaded tarball is checked against `sha256` or, if that isn't given,
+/// a `.sha256` checksum file published with the release before unpacking it.
+/// Without either the download fails, unless `allow_unverified` is set.
+pub fn download_compiler(
+    version: &str,
+    sha256: Option<&str>,
+    platform: Option<Platform>,
+    offline: bool,
+    allow_unverified: bool,
+) -> Result<PathBuf> {
+    let platform = match platform {
+        Some(platform) => platform,
+        None => Platform::host()?,
+    };
+    let version_dir = Path::new(".vessel")
+        .join(".bin")
+        .join(validate_version(version)?);
+    if is_unkeyed_compiler(&version_dir) {
+        adopt_unkeyed_compiler(&version_dir)?;
+    }
+    let dest = version_dir.join(platform.to_string());
     if dest.exists() {
+        record_use(&dest);
         return Ok(dest);
     }
+    if offline {
+        return Err(VesselError::NotAvailableOffline {
+            name: "moc".to_string(),
+            version: version.to_string(),
+        }
+        .into());
+    }
 
     let tmp = Path::new(".vessel").join(".tmp");
     if !tmp.exists() {
         fs::create_dir_all(&tmp)?
     }
 
-    let os = if cfg!(target_os = "linux") {
-        ("x86_64-linux", "Linux-x86_64")
-    } else if cfg!(target_os = "macos") {
-        ("x86_64-darwin", "Darwin-x86_64")
-    } else {
-        return Err(anyhow::anyhow!(
-            "Installing the compiler is only supported on Linux or MacOS for now"
-        ));
-    };
-    let target = match Version::parse(version) {
-        Ok(semver) if semver > Version::new(0, 6, 2) => format!(
-            "https://github.com/dfinity/motoko/releases/download/{}/motoko-{}-{}.tar.gz",
-            version, os.1, version
-        ),
-        _ => format!(
-            "https://download.dfinity.systems/motoko/{}/{}/motoko-{}.tar.gz",
-            version, os.0, version
-        ),
-    };
-
-    let client = reqwest::blocking::Client::new();
-    let response = client
-        .get(target)
-        .header(reqwest::header::USER_AGENT, "vessel")
-        .send()?;
-
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!(
-            "Failed to download Motoko binaries for version {}, with \"{}\"\n\nDetails: {}",
-            version,
-            response.status(),
-            response
-                .text()
-                .unwrap_or_else(|_| "No more details".to_string())
-        ));
+    let target = platform.compiler_url(version)?;
+    let response = http::get(&target).context(format!(
+        "Failed to download Motoko binaries for version {version}"
+    ))?;
+
+    // The tarball is only unpacked once its checksum was verified, so a
+    // mismatch leaves nothing behind in `.vessel/.bin`
+    let mut tarball = tempfile::tempfile_in(&tmp)?;
+    let mut hasher = Sha256::new();
+    let progress = Progress::new("downloaded", 1);
+    let name = format!("moc {version}");
+    let mut reader = progress.reader(&name, response);
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buf).context(format!(
+            "Failed to download Motoko binaries for version {version}"
+        ))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        tarball.write_all(&buf[..read])?;
     }
+    progress.finish(&name);
+    let found = format!("sha256:{:x}", hasher.finalize());
+    let expected = match sha256 {
+        Some(sha256) => Some(sha256.to_string()),
+        None => fetch_compiler_checksum(&target)?,
+    };
+    check_compiler_checksum(version, expected, found, allow_unverified)?;
+    tarball.rewind()?;
 
     // We unpack into a temporary directory and rename it in one go once
     // the full unpacking was successful
     let tmp_dir: TempDir = tempfile::tempdir_in(tmp)?;
-    Archive::new(GzDecoder::new(response)).unpack(tmp_dir.path())?;
+    Archive::new(GzDecoder::new(tarball)).unpack(tmp_dir.path())?;
 
-    if !bin.exists() {
-        fs::create_dir_all(&bin)?
+    if !version_dir.exists() {
+        fs::create_dir_all(&version_dir)?
+    }
+    if dest.exists() {
+        fs::remove_dir_all(&dest)?;
     }
     fs::rename(tmp_dir, &dest)?;
+    record_use(&dest);
 
     Ok(dest)
 }
 
-/// Downloads a package either as a tar-ball from Github or clones it as a repo
-pub fn download_package(package: &Package, force: bool) -> Result<PathBuf> {
-    let vessel_dir = Path::new(".vessel");
-    // Always validate the name here
-    let package_dir = vessel_dir.join(validate_name(&package.name));
-    if !package_dir.exists() {
-        fs::create_dir_all(&package_dir).context(format!(
-            "Failed to create the package directory at {}",
-            package_dir.display()
-        ))?;
-    }
-    // Always validate the version here
-    let repo_dir = package_dir.join(validate_version(&package.version));
-    if force && repo_dir.exists() {
-        fs::remove_dir_all(&repo_dir)?;
-    }
-    if !repo_dir.exists() {
-        let tmp = Path::new(".vessel").join(".tmp");
-        if !tmp.exists() {
-            fs::create_dir_all(&tmp)?
-        }
-        if package.repo.starts_with("https://github.com") {
-            info!("Downloading tar-ball: \"{}\"", package.name);
-            download_tar_ball(&tmp, &repo_dir, &package.repo, &package.version).or_else(|_| {
-                warn!(
-                    "Downloading tar-ball failed, cloning as git repo instead: \"{}\"",
-                    package.name
-                );
-                clone_package(&tmp, &repo_dir, &package.repo, &package.version)
-            })?
-        } else {
-            info!("Cloning git repository: \"{}\"", package.name);
-            clone_package(&tmp, &repo_dir, &package.repo, &package.version)?
+/// Whether `version_dir` holds a compiler installed straight into
+/// `.vessel/.bin/<version>`, before installs were kept apart by platform
+fn is_unkeyed_compiler(version_dir: &Path) -> bool {
+    version_dir.join("moc").is_file() || version_dir.join(PLATFORM_FILE).is_file()
+}
+
+/// Moves a compiler installed into `version_dir` by an older vessel to
+/// `<version_dir>/<platform>`. Those installs recorded their platform in
+/// `PLATFORM_FILE`, or predate it and were made for the host.
+fn adopt_unkeyed_compiler(version_dir: &Path) -> Result<()> {
+    let platform = match fs::read_to_string(version_dir.join(PLATFORM_FILE)) {
+        Ok(platform) => platform
+            .trim()
+            .parse()
+            .map_err(|err| anyhow::anyhow!("{}", err))?,
+        Err(_) => Platform::host()?,
+    };
+    let tmp = Path::new(".vessel").join(".tmp");
+    fs::create_dir_all(&tmp)?;
+    let moved = tempfile::tempdir_in(&tmp)?;
+    let staged = moved.path().join(platform.to_string());
+    if let Err(err) = fs::rename(version_dir, &staged) {
+        // Another vessel process adopted it first
+        if !is_unkeyed_compiler(version_dir) {
+            return Ok(());
         }
-    } else {
-        debug!(
-            "{} at version {} has already been downloaded",
-            package.name, package.version
-        )
+        return Err(err.into());
     }
-    Ok(repo_dir.join("src"))
+    let _ = fs::remove_file(staged.join(PLATFORM_FILE));
+    fs::create_dir_all(version_dir)?;
+    fs::rename(&staged, version_dir.join(platform.to_string())).context(format!(
+        "Failed to move the compiler in {} to its platform directory",
+        version_dir.display()
+    ))?;
+    Ok(())
 }
 
-/// Downloads and unpacks a tar-ball from Github into the `dest` path
-fn download_tar_ball(tmp: &Path, dest: &Path, repo: &str, version: &str) -> Result<()> {
-    let target = format!(
-        "{}/archive/{}/.tar.gz",
-        repo.trim_end_matches(".git"),
-        version
-    );
-    let response = reqwest::blocking::get(target)?;
-
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!(
-            "Failed to download tarball for repo \"{}\" at version \"{}\", with \"{}\"\n\nDetails: {}",
-            repo,
-            version,
-            response.status(),
-            response.text().unwrap_or_else(|_| "No more details".to_string())
-        ));
+/// Records that the compiler in `dir` was used, so `Vessel::gc` keeps it
+fn record_use(dir: &Path) {
+    let touched = fs::File::create(dir.join(LAST_USED_FILE))
+        .and_then(|file| file.set_modified(SystemTime::now()));
+    if let Err(err) = touched {
+        debug!("Failed to record the use of {}: {}", dir.display(), err);
     }
+}
 
-    // We unpack into a temporary directory and rename it in one go once
-    // the full unpacking was successful
+/// Downloads the `.sha256` checksum file published next to a compiler
+/// release. Only a missing file means there is no checksum, any other
+/// failure to get it fails the install.
+fn fetch_compiler_checksum(target: &str) -> Result<Option<Hash>> {
+    let url = format!("{target}.sha256");
+    let text = match http::get_text(&url) {
+        Ok(text) => text,
+        Err(err) => {
+            return match err.downcast_ref::<VesselError>() {
+                Some(VesselError::DownloadFailed {
+                    status: Some(404), ..
+                }) => Ok(None),
+                _ => Err(err.context("Failed to download the checksum of the Motoko compiler")),
+            }
+        }
+    };
+    // Checksum files look like `<hex>  <file name>`
+    let hex = text
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow::anyhow!("Invalid checksum file {}", url));
+    }
+    Ok(Some(format!("sha256:{hex}")))
+}
+
+/// Checks the checksum `found` of the compiler tarball at `version` against
+/// the `expected` one. Without an expected checksum the tarball can't be
+/// trusted, unless `allow_unverified` says so.
+fn check_compiler_checksum(
+    version: &str,
+    expected: Option<Hash>,
+    found: Hash,
+    allow_unverified: bool,
+) -> Result<()> {
+    match expected {
+        Some(expected) if !same_hash(&expected, &found) => Err(VesselError::CompilerHashMismatch {
+            version: version.to_string(),
+            expected,
+            found,
+        }
+        .into()),
+        Some(_) => Ok(()),
+        None if allow_unverified => {
+            warn!(
+                "Installing version {} of the Motoko compiler without verifying it, its checksum is {}",
+                version, found
+            );
+            Ok(())
+        }
+        None => Err(VesselError::CompilerChecksumMissing {
+            version: version.to_string(),
+            found,
+        }
+        .into()),
+    }
+}
+
+/// Downloads a package either as a tar-ball from Github or clones it as a repo
+pub fn download_package(package: &Package, force: bool, offline: bool) -> Result<PathBuf> {
+    install_package(package, force, None, offline, None)?;
+    package.install_path()
+}
+
+/// The file older vessels wrote into `.vessel/.bin/<version>` to record the
+/// platform of the compiler installed there
+const PLATFORM_FILE: &str = ".vessel-platform";
+
+/// The file inside an installed package's directory that records what was installed
+const INSTALL_MARKER: &str = ".vessel-install";
+
+/// The commit and source hash recorded for an installed package
+#[derive(Debug, Serialize, Deserialize)]
+struct InstallMarker {
+    commit: String,
+    hash: Hash,
+}
+
+impl InstallMarker {
+    /// Reads the marker of the installation in `dir`, if there is one
+    fn read(dir: &Path) -> Option<InstallMarker> {
+        let marker = fs::read_to_string(dir.join(INSTALL_MARKER)).ok()?;
+        serde_json::from_str(&marker).ok()
+    }
+}
+
+/// Returns the directory of the user-level package cache that is shared
+/// between projects. `VESSEL_HOME` takes precedence over `XDG_CACHE_HOME`.
+pub fn cache_dir() -> Option<PathBuf> {
+    let var = |name: &str| env::var_os(name).filter(|v| !v.is_empty());
+    var("VESSEL_HOME")
+        .map(PathBuf::from)
+        .or_else(|| var("XDG_CACHE_HOME").map(|dir| PathBuf::from(dir).join("vessel")))
+        .or_else(|| var("HOME").map(|dir| PathBuf::from(dir).join(".cache").join("vessel")))
+}
+
+/// Where a package is stored inside the cache. Different package sets might
+/// use the same name and version for different repos, so the repo is part of
+/// the key.
+fn cache_entry(cache: &Path, package: &Package) -> Result<PathBuf> {
+    // Checkouts with and without submodules differ, so they are kept apart
+    let key = if package.submodules {
+        format!("{}#submodules", package.repo)
+    } else {
+        package.repo.clone()
+    };
+    let repo_hash = format!("{:x}", Sha256::digest(key.as_bytes()));
+    Ok(cache
+        .join("packages")
+        .join(validate_name(&package.name)?)
+        .join(format!(
+            "{}-{}",
+            validate_version(&package.version)?,
+            &repo_hash[..16]
+        )))
+}
+
+/// Installs a package into the project's `.vessel` directory and returns the
+/// commit it resolved to together with the hash of its sources. When `locked`
+/// is given the package is downloaded at the locked commit and its sources
+/// must match the locked hash.
+///
+/// Packages are downloaded into the shared cache first and then linked into
+/// the project. In `offline` mode only packages that are already installed or
+/// cached are used.
+fn install_package(
+    package: &Package,
+    force: bool,
+    locked: Option<&LockedPackage>,
+    offline: bool,
+    progress: Option<&Progress>,
+) -> Result<LockedPackage> {
+    let vessel_dir = Path::new(".vessel");
+    // Always validate the name here
+    let package_dir = vessel_dir.join(validate_name(&package.name)?);
+    if !package_dir.exists() {
+        fs::create_dir_all(&package_dir).context(format!(
+            "Failed to create the package directory at {}",
+            package_dir.display()
+        ))?;
+    }
+    // Always validate the version here
+    let repo_dir = package_dir.join(validate_version(&package.version)?);
+    if force && repo_dir.exists() {
+        fs::remove_dir_all(&repo_dir)?;
+    }
+    if let Some(installed) = check_installed(package, &repo_dir, locked, offline) {
+        return Ok(installed);
+    }
+
+    let cache = cache_dir();
+    let entry = match &cache {
+        None => None,
+        Some(cache) => Some(cache_entry(cache, package)?),
+    };
+    if let Some(entry) = entry.as_ref().filter(|entry| force && entry.exists()) {
+        fs::remove_dir_all(entry)?;
+    }
+    let cached = entry
+        .as_ref()
+        .and_then(|entry| check_installed(package, entry, locked, offline));
+    if offline && cached.is_none() {
+        return Err(VesselError::NotAvailableOffline {
+            name: package.name.clone(),
+            version: package.version.clone(),
+        }
+        .into());
+    }
+
+    if repo_dir.exists() {
+        fs::remove_dir_all(&repo_dir)?;
+    }
+    let tmp = vessel_dir.join(".tmp");
+    if !tmp.exists() {
+        fs::create_dir_all(&tmp)?
+    }
+    let (entry, installed) = match (cache, entry, cached) {
+        (Some(_), Some(entry), Some(installed)) => {
+            debug!("Using cached \"{}\" from {}", package.name, entry.display());
+            (entry, installed)
+        }
+        (Some(cache), Some(entry), None) => {
+            let cache_tmp = cache.join(".tmp");
+            if !cache_tmp.exists() {
+                fs::create_dir_all(&cache_tmp).context(format!(
+                    "Failed to create the package cache at {}",
+                    cache_tmp.display()
+                ))?;
+            }
+            if entry.exists() {
+                fs::remove_dir_all(&entry)?;
+            }
+            fs::create_dir_all(entry.parent().unwrap())?;
+            let installed = fetch_package(package, locked, &cache_tmp, &entry, progress)?;
+            (entry, installed)
+        }
+        _ => return fetch_package(package, locked, &tmp, &repo_dir, progress),
+    };
+    copy_tree(&tmp, &entry, &repo_dir)?;
+    Ok(installed)
+}
+
+/// Checks whether `dir` already contains an installation of `package` that
+/// matches its `sha256` and the `locked` entry. Installations that weren't
+/// recorded by vessel are adopted where `adopt_install` allows it.
+fn check_installed(
+    package: &Package,
+    dir: &Path,
+    locked: Option<&LockedPackage>,
+    offline: bool,
+) -> Option<LockedPackage> {
+    if !dir.exists() {
+        return None;
+    }
+    let marker = InstallMarker::read(dir).or_else(|| adopt_install(package, dir, locked, offline));
+    match (marker, locked) {
+        (Some(marker), _) if !package.matches_hash(&marker.hash) => info!(
+            "Installed \"{}\" does not match its sha256 in the package set, reinstalling",
+            package.name
+        ),
+        (Some(marker), Some(locked))
+            if marker.commit == locked.commit && marker.hash == locked.hash =>
+        {
+            debug!(
+                "{} at version {} has already been downloaded",
+                package.name, package.version
+            );
+            return Some(locked.clone());
+        }
+        (Some(marker), None) => {
+            debug!(
+                "{} at version {} has already been downloaded",
+                package.name, package.version
+            );
+            return Some(mk_locked_package(package, marker.commit, marker.hash));
+        }
+        (Some(_), Some(_)) => info!(
+            "Installed \"{}\" does not match {}, reinstalling",
+            package.name, LOCK_FILE
+        ),
+        (None, _) => info!(
+            "Installed \"{}\" was not recorded by vessel, reinstalling",
+            package.name
+        ),
+    }
+    None
+}
+
+/// Records an installation without an install marker, like the ones made by
+/// earlier versions of vessel, if its sources match the `locked` hash or the
+/// package's `sha256`, or if its git checkout tells which commit it is. In
+/// `offline` mode it is used as it is, since there's nothing to replace it
+/// with. Installs from tar-balls don't know their commit, so the version
+/// stands in for it.
+fn adopt_install(
+    package: &Package,
+    dir: &Path,
+    locked: Option<&LockedPackage>,
+    offline: bool,
+) -> Option<InstallMarker> {
+    let hash = hash_tree(&dir.join("src")).ok()?;
+    if !package.matches_hash(&hash) {
+        return None;
+    }
+    let checkout = || {
+        // Without the check git would report the commit of the project's repo
+        if !dir.join(".git").exists() {
+            return None;
+        }
+        let commit = git(dir, &package.repo, &["rev-parse", "HEAD"]).ok()?;
+        Some(commit.trim().to_string())
+    };
+    let commit = match locked {
+        Some(locked) if locked.hash == hash => locked.commit.clone(),
+        Some(_) => return None,
+        None => match checkout() {
+            Some(commit) => commit,
+            None if package.sha256.is_some() || offline => package.version.clone(),
+            None => return None,
+        },
+    };
+    info!(
+        "Adopting \"{}\" at version {}, which was installed before vessel recorded installs",
+        package.name, package.version
+    );
+    let marker = InstallMarker { commit, hash };
+    if let Err(err) = fs::write(
+        dir.join(INSTALL_MARKER),
+        serde_json::to_string(&marker).ok()?,
+    ) {
+        debug!(
+            "Failed to record the install of \"{}\": {}",
+            package.name, err
+        );
+    }
+    Some(marker)
+}
+
+/// Downloads a package into `dest`, using `tmp` as scratch space
+fn fetch_package(
+    package: &Package,
+    locked: Option<&LockedPackage>,
+    tmp: &Path,
+    dest: &Path,
+    progress: Option<&Progress>,
+) -> Result<LockedPackage> {
+    // We unpack into a temporary directory and rename it in one go once
+    // the package was fully downloaded and checked
     let tmp_dir: TempDir = tempfile::tempdir_in(tmp)?;
-    Archive::new(GzDecoder::new(response)).unpack(tmp_dir.path())?;
+    let rev = locked.map_or(package.version.as_str(), |l| l.commit.as_str());
+    // Github's tar-balls don't contain submodules
+    let (staged_dir, commit) =
+        if package.repo.starts_with("https://github.com") && !package.submodules {
+            info!("Downloading tar-ball: \"{}\"", package.name);
+            download_tar_ball(tmp_dir.path(), package, rev, progress).or_else(|_| {
+                warn!(
+                    "Downloading tar-ball failed, cloning as git repo instead: \"{}\"",
+                    package.name
+                );
+                let clone_dir = tmp_dir.path().join("clone");
+                fs::create_dir(&clone_dir)?;
+                clone_package(&clone_dir, package, rev)
+            })?
+        } else {
+            info!("Cloning git repository: \"{}\"", package.name);
+            clone_package(tmp_dir.path(), package, rev)?
+        };
+
+    let hash = hash_tree(&staged_dir.join("src")).context(format!(
+        "Failed to hash the sources of \"{}\"",
+        package.name
+    ))?;
+    if !package.matches_hash(&hash) {
+        return Err(VesselError::HashMismatch {
+            package: package.name.clone(),
+            expected: package.sha256.clone().unwrap_or_default(),
+            found: hash,
+        }
+        .into());
+    }
+    if let Some(locked) = locked {
+        if locked.hash != hash {
+            return Err(VesselError::LockMismatch {
+                package: package.name.clone(),
+                expected: locked.hash.clone(),
+                found: hash,
+            }
+            .into());
+        }
+    }
+    let marker = InstallMarker {
+        commit: commit.clone(),
+        hash: hash.clone(),
+    };
+    fs::write(
+        staged_dir.join(INSTALL_MARKER),
+        serde_json::to_string(&marker)?,
+    )?;
+    if let Err(err) = fs::rename(&staged_dir, dest) {
+        // Another vessel process might have installed the same package in the
+        // meantime, then its installation is the one that's in `dest`
+        return match InstallMarker::read(dest) {
+            Some(marker)
+                if package.matches_hash(&marker.hash)
+                    && locked.iter().all(|locked| locked.hash == marker.hash) =>
+            {
+                Ok(mk_locked_package(package, marker.commit, marker.hash))
+            }
+            _ => Err(err).context(format!(
+                "Failed to install \"{}\" into {}",
+                package.name,
+                dest.display()
+            )),
+        };
+    }
+
+    Ok(mk_locked_package(package, commit, hash))
+}
+
+/// Recreates the directory tree at `src` in `dest`. Files are copied rather
+/// than hardlinked, so editing them in `dest` can't change `src`.
+fn copy_tree(tmp: &Path, src: &Path, dest: &Path) -> Result<()> {
+    let tmp_dir: TempDir = tempfile::tempdir_in(tmp)?;
+    let staged_dir = tmp_dir.path().join("package");
+    for entry in WalkDir::new(src) {
+        let entry = entry?;
+        let target = staged_dir.join(entry.path().strip_prefix(src)?);
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+            #[cfg(not(unix))]
+            fs::copy(entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target).context(format!(
+                "Failed to copy {} to {}",
+                entry.path().display(),
+                target.display()
+            ))?;
+        }
+    }
+    if let Err(err) = fs::rename(&staged_dir, dest) {
+        // Another vessel process might have copied the same package in the
+        // meantime, then its copy is kept and ours is dropped with `tmp_dir`
+        let copied = InstallMarker::read(dest).map(|marker| marker.hash);
+        if copied.is_some() && copied == InstallMarker::read(src).map(|marker| marker.hash) {
+            return Ok(());
+        }
+        return Err(err).context(format!(
+            "Failed to copy {} to {}",
+            src.display(),
+            dest.display()
+        ));
+    }
+    Ok(())
+}
+
+fn mk_locked_package(package: &Package, commit: String, hash: Hash) -> LockedPackage {
+    LockedPackage {
+        name: package.name.clone(),
+        repo: package.repo.clone(),
+        version: package.version.clone(),
+        commit,
+        hash,
+    }
+}
+
+/// Downloads and unpacks a tar-ball of `package` at `version` from Github
+/// into `tmp`. Returns the unpacked repo directory and the commit the
+/// tar-ball was created from.
+fn download_tar_ball(
+    tmp: &Path,
+    package: &Package,
+    version: &str,
+    progress: Option<&Progress>,
+) -> Result<(PathBuf, String)> {
+    let repo = &package.repo;
+    let repo_path = repo.trim_end_matches(".git");
+    let archive = format!("{repo_path}/archive/{version}/.tar.gz");
+    // Archives of private repos are only served through the API, so we use
+    // it whenever there's a token for it and no mirror is configured
+    let api = "https://api.github.com/repos/";
+    let target = match repo_path.strip_prefix("https://github.com/") {
+        Some(name)
+            if config::rewrite_url(&archive)? == archive
+                && auth::credential_for(api)?.is_some() =>
+        {
+            format!("{api}{name}/tarball/{version}")
+        }
+        _ => archive,
+    };
+    let response = http::get(&target).context(format!(
+        "Failed to download tarball for repo \"{repo}\" at version \"{version}\""
+    ))?;
+
+    let response: Box<dyn Read + '_> = match progress {
+        None => response,
+        Some(progress) => Box::new(progress.reader(&package.name, response)),
+    };
+
+    let commit = unpack_tar_ball(GzDecoder::new(response), tmp)?.ok_or_else(|| {
+        anyhow::anyhow!("The tarball for \"{}\" does not record its commit", repo)
+    })?;
 
     // We expect an unpacked repo to contain exactly one directory
-    let repo_dir = match fs::read_dir(tmp_dir.path())?.next() {
+    let repo_dir = match fs::read_dir(tmp)?.next() {
         None => return Err(anyhow::anyhow!("Unpacked an empty tarball for {}", repo)),
         Some(dir) => dir?,
     };
@@ -456,100 +1777,328 @@ fn download_tar_ball(tmp: &Path, dest: &Path, repo: &str, version: &str) -> Resu
     if !repo_dir.path().is_dir() {
         return Err(anyhow::anyhow!("Failed to unpack tarball for \"{}\"", repo));
     }
-    fs::rename(repo_dir.path(), dest)?;
 
-    Ok(())
+    Ok((repo_dir.path(), commit))
 }
 
-/// Clones `repo` into `dest` and checks out `version`
-fn clone_package(tmp: &Path, dest: &Path, repo: &str, version: &str) -> Result<()> {
-    let tmp_dir: TempDir = tempfile::tempdir_in(tmp)?;
-    let clone_result = Command::new("git")
-        .args(["clone", repo, "repo"])
-        .current_dir(tmp_dir.path())
-        .output()
-        .context(format!("Failed to clone the repo at {repo}"))?;
-    if !clone_result.status.success() {
-        return Err(anyhow::anyhow!(
-            "Failed to clone the repo at: {}\nwith:\n{}",
-            repo,
-            std::str::from_utf8(&clone_result.stderr).unwrap()
-        ));
+/// Unpacks a tar archive into `dest` and returns the commit recorded in its
+/// global pax header. Github stores the commit an archive was created from in
+/// the header's comment, same as `git archive` does.
+fn unpack_tar_ball<R: Read>(reader: R, dest: &Path) -> Result<Option<String>> {
+    let mut commit = None;
+    let mut archive = Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_pax_global_extensions() {
+            if let Some(extensions) = entry.pax_extensions()? {
+                for extension in extensions {
+                    let extension = extension?;
+                    if extension.key() == Ok("comment") {
+                        commit = extension.value().ok().map(|v| v.trim().to_string());
+                    }
+                }
+            }
+            continue;
+        }
+        entry.unpack_in(dest)?;
     }
+    Ok(commit)
+}
 
-    let repo_dir = tmp_dir.path().join("repo");
-    let checkout_result = Command::new("git")
-        .args(["-c", "advice.detachedHead=false", "checkout", version])
-        .current_dir(&repo_dir)
-        .output()
-        .context(format!(
-            "Failed to checkout version {} for the repository {} in {}",
-            version,
-            repo,
-            repo_dir.display()
-        ))?;
-    if !checkout_result.status.success() {
-        return Err(anyhow::anyhow!(
-            "Failed to checkout version {} for the repo at: {}\nwith:\n{}",
-            version,
+/// Checks out `package` at `version` into `tmp`, including its submodules if
+/// the package asks for them. Returns the repo directory and the commit that
+/// was checked out.
+///
+/// Only `version` itself is fetched if the server allows it, otherwise, for
+/// example for commits that servers don't hand out on their own, the full
+/// repo is cloned.
+fn clone_package(tmp: &Path, package: &Package, version: &str) -> Result<(PathBuf, String)> {
+    let repo = package.repo.as_str();
+    let env = http::git_env(repo)?;
+    let repo_dir = tmp.join("repo");
+    let checkout = ["-c", "advice.detachedHead=false", "checkout", "-q"];
+    let shallow = git(tmp, repo, &["init", "-q", "repo"])
+        .and_then(|_| {
+            git_with_env(
+                &repo_dir,
+                repo,
+                &["fetch", "-q", "--depth", "1", repo, version],
+                &env,
+            )
+        })
+        .and_then(|_| git(&repo_dir, repo, &[&checkout[..], &["FETCH_HEAD"]].concat()));
+    if let Err(err) = shallow {
+        debug!(
+            "Shallow fetch of \"{}\" failed, cloning the full repo instead: {:#}",
+            package.name, err
+        );
+        if repo_dir.exists() {
+            fs::remove_dir_all(&repo_dir)?;
+        }
+        git_with_env(tmp, repo, &["clone", "-q", repo, "repo"], &env)?;
+        git(&repo_dir, repo, &[&checkout[..], &[version]].concat())?;
+    }
+    if package.submodules {
+        git_with_env(
+            &repo_dir,
             repo,
-            std::str::from_utf8(&checkout_result.stderr).unwrap()
-        ));
+            &["submodule", "update", "-q", "--init", "--recursive"],
+            &env,
+        )?;
+        remove_gitlinks(&repo_dir)?;
     }
+    let commit = git(&repo_dir, repo, &["rev-parse", "HEAD"])?
+        .trim()
+        .to_string();
+    Ok((repo_dir, commit))
+}
 
-    fs::rename(repo_dir, dest)?;
+/// Removes the `.git` files that point checked out submodules to their git
+/// directory. They contain absolute paths, which would change the hash of
+/// the package's sources.
+fn remove_gitlinks(repo_dir: &Path) -> Result<()> {
+    let gitlinks: Vec<PathBuf> = WalkDir::new(repo_dir)
+        .min_depth(2)
+        .into_iter()
+        .filter_entry(|entry| entry.path() != repo_dir.join(".git"))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name() == ".git" && entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .collect();
+    for gitlink in gitlinks {
+        fs::remove_file(gitlink)?;
+    }
     Ok(())
 }
 
+/// Runs git with `args` inside `dir` and returns its output. `repo` is only
+/// used for error reporting.
+fn git(dir: &Path, repo: &str, args: &[&str]) -> Result<String> {
+    git_with_env(dir, repo, args, &[])
+}
+
+/// Like `git`, but sets the environment variables `envs`
+fn git_with_env(
+    dir: &Path,
+    repo: &str,
+    args: &[&str],
+    envs: &[(String, String)],
+) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .envs(envs.iter().map(|(key, value)| (key, value)))
+        .current_dir(dir)
+        .output()
+        .context(format!("Failed to run git for the repo at {repo}"))?;
+    if !output.status.success() {
+        return Err(VesselError::GitFailed {
+            repo: repo.to_string(),
+            command: args.join(" "),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }
+        .into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
 #[derive(Deserialize)]
 struct GhRelease {
     tag_name: String,
 }
 
-type Hash = String;
+pub type Hash = String;
 
 /// Fetches the latest release of dfinity/vessel-package-set and computes its
 /// Dhall hash. This way it can be used to initialize the package-set file.
 pub fn fetch_latest_package_set() -> Result<(Url, Hash)> {
-    let client = reqwest::blocking::Client::new();
     let url = "https://api.github.com/repos/dfinity/vessel-package-set/releases";
-    let response = client
-        .get(url)
-        .header(reqwest::header::ACCEPT, "application/vnd.github.v3+json")
-        .header(reqwest::header::USER_AGENT, "vessel")
-        .send()?;
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!(
-            "Failed to read Github releases: {:#?}",
-            response
-        ));
-    }
-    let releases: Vec<GhRelease> = response.json()?;
+    let releases: Vec<GhRelease> = http::get_json(url).context("Failed to read Github releases")?;
     let release = &releases.first().ok_or_else(|| anyhow::anyhow!("Unable to find any vessel-package-set releases from {}.\nPlease try again in a few minutes or open an issue at https://github.com/dfinity/vessel/issues.", url))?.tag_name;
-    fetch_package_set_impl(&client, release)
+    fetch_package_set(release)
 }
 
-/// Like `fetch_latest_package_set`, but lets you specify the tag
+/// Like `fetch_latest_package_set`, but lets you specify the tag. The returned
+/// URL is the canonical GitHub one, the configured mirrors only apply to the
+/// download. The package set is stored in the Dhall cache under its hash, so
+/// importing the returned URL with the hash doesn't go to GitHub either.
 pub fn fetch_package_set(tag: &str) -> Result<(Url, Hash)> {
-    let client = reqwest::blocking::Client::new();
-    fetch_package_set_impl(&client, tag)
-}
-
-fn fetch_package_set_impl(client: &reqwest::blocking::Client, tag: &str) -> Result<(Url, Hash)> {
     let package_set_url = format!(
         "https://github.com/dfinity/vessel-package-set/releases/download/{tag}/package-set.dhall"
     );
-    let package_set = client
-        .get(&package_set_url)
-        .send()
-        .context("When downloading the package set release")?
-        .text()
-        .context("When decoding the package set release")?;
+    let package_set =
+        http::get_text(&package_set_url).context("When downloading the package set release")?;
     let hash = hash_dhall_expression(&package_set).context("When hashing the package set")?;
+    cache_dhall_expression(&package_set, &hash).context("When caching the package set")?;
     Ok((package_set_url, hash))
 }
 
-/// Computes the sha256 hash for a given Dhall expression
+/// Adds `expr` to the Dhall cache under `hash`. Dhall fills its cache when it
+/// resolves an import with a hash, so this imports a copy of `expr`.
+fn cache_dhall_expression(expr: &str, hash: &str) -> Result<()> {
+    let mut file = tempfile::Builder::new().suffix(".dhall").tempfile()?;
+    file.write_all(expr.as_bytes())?;
+    serde_dhall::from_str(&format!("{} {}", file.path().display(), hash)).parse::<SimpleValue>()?;
+    Ok(())
+}
+
+/// Reads all packages from a package set file. Unlike `Vessel::new` it keeps
+/// the `name@range` dependencies as they are written, so changed ranges show
+/// up when comparing package sets.
+pub fn read_package_set(package_set_file: &Path) -> Result<Vec<Package>> {
+    parse_typed(DhallSource::File(package_set_file), |source| {
+        VesselError::PackageSetParse {
+            path: package_set_file.to_path_buf(),
+            source,
+        }
+    })
+}
+
+/// Like `read_package_set`, but reads the file as of the git revision `rev`.
+/// The whole repo is checked out at `rev` into a temporary directory first,
+/// so relative imports resolve to the files as they were at `rev` as well.
+pub fn read_package_set_at(package_set_file: &Path, rev: &str) -> Result<Vec<Package>> {
+    let dir = match package_set_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = package_set_file.file_name().ok_or_else(|| {
+        anyhow::anyhow!("Invalid package set file {}", package_set_file.display())
+    })?;
+    let repo = package_set_file.display().to_string();
+    let root = PathBuf::from(git(dir, &repo, &["rev-parse", "--show-toplevel"])?.trim());
+    let prefix = git(dir, &repo, &["rev-parse", "--show-prefix"])?;
+
+    let tmp_dir = tempfile::tempdir()?;
+    let archive = tmp_dir.path().join("archive.tar");
+    git(
+        &root,
+        &repo,
+        &[
+            "archive",
+            "--format=tar",
+            "--output",
+            &archive.display().to_string(),
+            rev,
+        ],
+    )?;
+    let tree = tmp_dir.path().join("tree");
+    Archive::new(fs::File::open(&archive)?).unpack(&tree)?;
+    read_package_set(&tree.join(prefix.trim()).join(file_name)).context(format!(
+        "Failed to read {} at {}",
+        package_set_file.display(),
+        rev
+    ))
+}
+
+/// Points the `upstream` binding in `package_set_file` at the package set at
+/// `url` with the given `hash`, leaving the rest of the file alone. Returns
+/// the changes between the old and the new upstream package set, or `None` if
+/// the old one couldn't be loaded.
+pub fn write_upstream(
+    package_set_file: &Path,
+    url: &str,
+    hash: &str,
+) -> Result<Option<Vec<PackageChange>>> {
+    let source = fs::read_to_string(package_set_file).context(format!(
+        "Failed to read the package set file {}",
+        package_set_file.display()
+    ))?;
+    let import = format!("{url} {hash}");
+    let (edited, old_import) = manifest::replace_upstream(&source, &import).context(format!(
+        "Failed to update the package set file {}",
+        package_set_file.display()
+    ))?;
+    let base = package_set_file.parent().unwrap_or(Path::new(""));
+    let new = load_package_set_import(base, &import)?;
+    let old = match load_package_set_import(base, &old_import) {
+        Ok(old) => Some(old),
+        Err(err) => {
+            warn!("Failed to load the previous upstream package set, can't summarize the changes.\n\nDetails: {:#}", err);
+            None
+        }
+    };
+    fs::write(package_set_file, edited).context(format!(
+        "Failed to write the package set file {}",
+        package_set_file.display()
+    ))?;
+    Ok(old.map(|old| diff_package_sets(&old, &new)))
+}
+
+/// Evaluates a Dhall import of a package set, relative imports are resolved
+/// against `base`
+fn load_package_set_import(base: &Path, import: &str) -> Result<Vec<Package>> {
+    let import = if import.starts_with("./") || import.starts_with("../") {
+        base.canonicalize()
+            .unwrap_or_else(|_| base.to_path_buf())
+            .join(import)
+            .display()
+            .to_string()
+    } else {
+        import.to_string()
+    };
+    parse_typed(DhallSource::Text(&import), anyhow::Error::from)
+        .context(format!("Failed to load the package set {import}"))
+}
+
+/// Where a Dhall expression is read from
+enum DhallSource<'a> {
+    File(&'a Path),
+    Text(&'a str),
+}
+
+/// Parses the Dhall expression in `source`, type checking it against the
+/// static type of `T`. Dhall records can't leave out fields, so the fields
+/// `source` doesn't have are dropped from the type first. That way fields
+/// with a default, like a package's `sha256`, can be left out, while
+/// misspelled fields still fail to type check. Parse errors are turned into
+/// the caller's error type with `error`.
+fn parse_typed<T, E>(source: DhallSource, error: impl Fn(serde_dhall::Error) -> E) -> Result<T>
+where
+    T: StaticType + DeserializeOwned,
+    anyhow::Error: From<E>,
+{
+    let value: SimpleValue = match source {
+        DhallSource::File(path) => serde_dhall::from_file(path).parse(),
+        DhallSource::Text(text) => serde_dhall::from_str(text).parse(),
+    }
+    .map_err(&error)?;
+    // An empty list has no entries to check, but the type it is annotated
+    // with might not have all the fields
+    if matches!(&value, SimpleValue::List(values) if values.is_empty()) {
+        return Ok(serde_dhall::from_simple_value(value).map_err(&error)?);
+    }
+    let ty = present_fields(T::static_type(), &value);
+    Ok(match source {
+        DhallSource::File(path) => serde_dhall::from_file(path).type_annotation(&ty).parse(),
+        DhallSource::Text(text) => serde_dhall::from_str(text).type_annotation(&ty).parse(),
+    }
+    .map_err(error)?)
+}
+
+/// Removes the record fields from `ty` that are missing in `value`
+fn present_fields(ty: SimpleType, value: &SimpleValue) -> SimpleType {
+    match (ty, value) {
+        (SimpleType::Record(fields), SimpleValue::Record(values)) => SimpleType::Record(
+            fields
+                .into_iter()
+                .filter_map(|(name, ty)| {
+                    let ty = present_fields(ty, values.get(&name)?);
+                    Some((name, ty))
+                })
+                .collect(),
+        ),
+        (SimpleType::List(ty), SimpleValue::List(values)) => match values.first() {
+            // Entries of a Dhall list all have the same type
+            Some(value) => SimpleType::List(Box::new(present_fields(*ty, value))),
+            None => SimpleType::List(ty),
+        },
+        (SimpleType::Optional(ty), SimpleValue::Optional(Some(value))) => {
+            SimpleType::Optional(Box::new(present_fields(*ty, value)))
+        }
+        (ty, _) => ty,
+    }
+}
+
 /// Computes the sha256 hash for a given Dhall expression
 fn hash_dhall_expression(expr: &str) -> Result<String> {
     let dhall_expr = dhall::syntax::text::parser::parse_expr(expr)
@@ -561,17 +2110,122 @@ fn hash_dhall_expression(expr: &str) -> Result<String> {
     Ok(formatted_hash)
 }
 
+/// Compares two sha256 hashes, ignoring case and an optional `sha256:` prefix
+fn same_hash(a: &str, b: &str) -> bool {
+    a.trim_start_matches("sha256:")
+        .eq_ignore_ascii_case(b.trim_start_matches("sha256:"))
+}
+
+/// Computes a deterministic sha256 hash over all files below `root`. Files are
+/// visited in sorted order and contribute their relative path and contents.
+pub fn hash_tree(root: &Path) -> Result<Hash> {
+    if !root.is_dir() {
+        return Err(anyhow::anyhow!("{} is not a directory", root.display()));
+    }
+    let mut hasher = Sha256::new();
+    for entry in WalkDir::new(root).sort_by_file_name() {
+        let entry = entry?;
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(root)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let contents = if file_type.is_symlink() {
+            fs::read_link(entry.path())?
+                .to_string_lossy()
+                .into_owned()
+                .into_bytes()
+        } else {
+            fs::read(entry.path())?
+        };
+        hasher.update(relative.as_bytes());
+        hasher.update([0]);
+        hasher.update((contents.len() as u64).to_le_bytes());
+        hasher.update(&contents);
+    }
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+/// The package set `init` writes, following the `upstream` import
+const PACKAGE_SET_TEMPLATE: &str = r#"
+let Package =
+      { Type =
+          { name : Text
+          , version : Text
+          , repo : Text
+          , dependencies : List Text
+          , sha256 : Optional Text
+          , submodules : Bool
+          }
+      , default =
+        { dependencies = [] : List Text, sha256 = None Text, submodules = False }
+      }
+
+-- The packages of the upstream package set leave out the optional fields
+let Upstream = { name : Text, version : Text, repo : Text, dependencies : List Text }
+
+let fromUpstream =
+      \(packages : List Upstream) ->
+        List/fold
+          Upstream
+          packages
+          (List Package.Type)
+          ( \(package : Upstream) ->
+            \(rest : List Package.Type) ->
+              [ Package.default // package ] # rest
+          )
+          ([] : List Package.Type)
+
+let
+  -- This is where you can add your own packages to the package-set
+  additions =
+    [] : List Package.Type
+
+let
+  {- This is where you can override existing packages in the package-set
+
+     For example, if you wanted to use version `v2.0.0` of the foo library:
+     let overrides = [
+         Package::{
+         , name = "foo"
+         , version = "v2.0.0"
+         , repo = "https://github.com/bar/foo"
+         }
+     ]
+  -}
+  overrides =
+    [] : List Package.Type
+
+in  fromUpstream upstream # additions # overrides
+"#;
+
 /// Initializes a new vessel project by creating a `vessel.dhall` file with no
-/// dependencies and adding a small package set referencing vessel-package-set
-pub fn init() -> Result<()> {
+/// dependencies and adding a small package set referencing vessel-package-set.
+/// In `offline` mode the package set falls back to a known release instead of
+/// looking up the latest one.
+pub fn init(offline: bool) -> Result<()> {
     let package_set_path: PathBuf = PathBuf::from("package-set.dhall");
     let manifest_path: PathBuf = PathBuf::from("vessel.dhall");
-    let (package_set_url, hash) = match fetch_latest_package_set() {
-        Ok(r) => r,
-        Err(e) => {
-            warn!("Failed to fetch latest package-set. Initializing with an older fallback version.\n\nDetails: {}", e);
-            ("https://github.com/dfinity/vessel-package-set/releases/download/mo-0.4.3-20200916/package-set.dhall".to_string(),
-             "sha256:3e1d8d20e35550bc711ae94f94da8b0091e3a3094f91874ff62686c070478dd7".to_string())
+    let fallback = || {
+        ("https://github.com/dfinity/vessel-package-set/releases/download/mo-0.4.3-20200916/package-set.dhall".to_string(),
+         "sha256:3e1d8d20e35550bc711ae94f94da8b0091e3a3094f91874ff62686c070478dd7".to_string())
+    };
+    let (package_set_url, hash) = if offline {
+        info!("Initializing with a fallback package-set version in offline mode.");
+        fallback()
+    } else {
+        match fetch_latest_package_set() {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("Failed to fetch latest package-set. Initializing with an older fallback version.\n\nDetails: {}", e);
+                fallback()
+            }
         }
     };
     if package_set_path.exists() {
@@ -594,34 +2248,7 @@ pub fn init() -> Result<()> {
     )?;
     let mut manifest = fs::File::create("package-set.dhall")?;
     write!(&mut manifest, "let upstream = {package_set_url} {hash}")?;
-    manifest.write_all(
-        br#"
-let Package =
-    { name : Text, version : Text, repo : Text, dependencies : List Text }
-
-let
-  -- This is where you can add your own packages to the package-set
-  additions =
-    [] : List Package
-
-let
-  {- This is where you can override existing packages in the package-set
-
-     For example, if you wanted to use version `v2.0.0` of the foo library:
-     let overrides = [
-         { name = "foo"
-         , version = "v2.0.0"
-         , repo = "https://github.com/bar/foo"
-         , dependencies = [] : List Text
-         }
-     ]
-  -}
-  overrides =
-    [] : List Package
-
-in  upstream # additions # overrides
-"#,
-    )?;
+    manifest.write_all(PACKAGE_SET_TEMPLATE.as_bytes())?;
     Ok(())
 }
 
@@ -637,19 +2264,34 @@ pub struct Package {
     pub repo: Url,
     pub version: Tag,
     pub dependencies: Vec<Name>,
+    /// The expected hash of the package's `src` directory, as computed by `hash_tree`
+    #[serde(default)]
+    pub sha256: Option<Hash>,
+    /// Whether to check out the git submodules of the package's repo
+    #[serde(default)]
+    pub submodules: bool,
 }
 
 impl Package {
-    pub fn install_path(&self) -> PathBuf {
-        Path::new(".vessel")
-            .join(validate_name(&self.name))
-            .join(validate_version(&self.version))
-            .join("src")
+    /// Checks a hash computed by `hash_tree` against this package's `sha256`.
+    /// Packages without a `sha256` match any hash.
+    pub fn matches_hash(&self, hash: &str) -> bool {
+        match &self.sha256 {
+            None => true,
+            Some(expected) => same_hash(expected, hash),
+        }
+    }
+
+    pub fn install_path(&self) -> Result<PathBuf> {
+        Ok(Path::new(".vessel")
+            .join(validate_name(&self.name)?)
+            .join(validate_version(&self.version)?)
+            .join("src"))
     }
 
     /// Returns all Motoko sources found inside this package's installation directory
-    pub fn sources(&self) -> impl Iterator<Item = PathBuf> {
-        WalkDir::new(self.install_path())
+    pub fn sources(&self) -> Result<impl Iterator<Item = PathBuf>> {
+        Ok(WalkDir::new(self.install_path()?)
             .into_iter()
             .filter_map(|e| match e {
                 Err(_) => None,
@@ -662,7 +2304,7 @@ impl Package {
                     }
                     None
                 }
-            })
+            }))
     }
 }
 
@@ -671,10 +2313,24 @@ impl Package {
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct PackageSet(pub HashMap<Name, Package>);
 
-#[derive(Debug, PartialEq, Eq, Default, Serialize, Deserialize, serde_dhall::StaticType)]
+#[derive(Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct Manifest {
     pub compiler: Option<String>,
     pub dependencies: Vec<Name>,
+    /// The expected hash of the compiler release tarball
+    #[serde(default, rename = "compilerSha256")]
+    pub compiler_sha256: Option<Hash>,
+}
+
+// The derived type would use the Rust field names instead of the renamed ones
+impl StaticType for Manifest {
+    fn static_type() -> SimpleType {
+        SimpleType::Record(HashMap::from([
+            ("compiler".to_string(), Option::<String>::static_type()),
+            ("dependencies".to_string(), Vec::<Name>::static_type()),
+            ("compilerSha256".to_string(), Option::<Hash>::static_type()),
+        ]))
+    }
 }
 
 impl PackageSet {
@@ -691,31 +2347,102 @@ impl PackageSet {
         self.0.get(name)
     }
 
-    fn find_unsafe(&self, name: &str) -> &Package {
+    /// Finds a package by name, `referrer` is the package that depends on it
+    fn find_dependency(&self, name: &str, referrer: Option<&str>) -> Result<&Package> {
         self.find(name)
-            .unwrap_or_else(|| panic!("Package \"{}\" wasn't specified in the package set", name))
+            .ok_or_else(|| self.unknown_package(name, referrer))
+    }
+
+    /// Creates an error for a package that isn't part of the package set,
+    /// suggesting similarly named packages
+    fn unknown_package(&self, name: &str, referrer: Option<&str>) -> anyhow::Error {
+        VesselError::UnknownPackage {
+            name: name.to_string(),
+            referrer: referrer.map(str::to_string),
+            suggestion: self.suggest(name).map(str::to_string),
+        }
+        .into()
+    }
+
+    /// Finds the package with the most similar name to `name`, if any is close enough
+    fn suggest(&self, name: &str) -> Option<&str> {
+        let max_distance = name.chars().count().max(3) / 3;
+        self.0
+            .keys()
+            .map(|candidate| (edit_distance(name, candidate), candidate.as_str()))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min()
+            .map(|(_, candidate)| candidate)
     }
 
     /// Finds all transitive dependencies starting from the given package names.
-    /// Includes the entry points in the resulting vector
-    fn transitive_deps(&self, entry_points: Vec<Name>) -> Vec<&Package> {
-        let mut found: HashSet<Name> = HashSet::new();
-        let mut todo: Vec<Name> = entry_points;
-        while let Some(next) = todo.pop() {
-            if !found.contains(&next) {
-                todo.append(&mut self.find_unsafe(&next).dependencies.clone());
-                found.insert(next);
+    /// Includes the entry points in the resulting vector. Fails if the
+    /// dependencies contain a cycle.
+    fn transitive_deps(&self, entry_points: Vec<Name>) -> Result<Vec<&Package>> {
+        if let Some(cycle) = self.find_cycle(entry_points.iter().map(String::as_str)) {
+            return Err(cycle_error(&cycle));
+        }
+        let mut found: HashMap<&str, &Package> = HashMap::new();
+        let mut todo: Vec<(Name, Option<&str>)> =
+            entry_points.into_iter().map(|name| (name, None)).collect();
+        while let Some((next, referrer)) = todo.pop() {
+            if !found.contains_key(next.as_str()) {
+                let package = self.find_dependency(&next, referrer)?;
+                todo.extend(
+                    package
+                        .dependencies
+                        .iter()
+                        .map(|dep| (dep.clone(), Some(package.name.as_str()))),
+                );
+                found.insert(&package.name, package);
             }
         }
         // Once we have incremental compilation we could return these toposorted to allow
         // starting to compile the first packages while others are still being downloaded.
         // For now we sort them to get deterministic behaviour for testing.
-        let mut found: Vec<Name> = found.into_iter().collect();
-        found.sort();
-        found.iter().map(|n| self.find_unsafe(n)).collect()
+        let mut found: Vec<&Package> = found.into_values().collect();
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(found)
+    }
+
+    /// Finds every dependency path from one of the `entry_points` to `target`.
+    /// Paths start with an entry point and end with `target`, they're empty if
+    /// `target` isn't a transitive dependency of the entry points.
+    pub fn dependency_paths(&self, entry_points: &[Name], target: &str) -> Result<Vec<Vec<&str>>> {
+        let install_plan = self.transitive_deps(entry_points.to_vec())?;
+        let mut parents: HashMap<&str, Vec<&str>> = HashMap::new();
+        for package in &install_plan {
+            for dep in &package.dependencies {
+                parents.entry(dep).or_default().push(&package.name);
+            }
+        }
+        let mut paths = vec![];
+        if let Some(package) = install_plan.iter().find(|package| package.name == target) {
+            collect_paths(
+                &package.name,
+                &parents,
+                entry_points,
+                &mut vec![],
+                &mut paths,
+            );
+        }
+        paths.sort();
+        Ok(paths)
     }
 
-    pub fn topo_sorted(&self) -> Vec<&Package> {
+    /// Sorts all packages so that every package comes after its dependencies.
+    /// Fails if the package set contains a dependency cycle or unknown dependencies.
+    pub fn topo_sorted(&self) -> Result<Vec<&Package>> {
+        let mut names: Vec<&str> = self.0.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        for name in &names {
+            for dep in &self.0[*name].dependencies {
+                self.find_dependency(dep, Some(name))?;
+            }
+        }
+        if let Some(cycle) = self.find_cycle(names) {
+            return Err(cycle_error(&cycle));
+        }
         let mut ts = TopologicalSort::<&str>::new();
         for (name, package) in &self.0 {
             ts.insert(name.as_str());
@@ -723,30 +2450,162 @@ impl PackageSet {
                 ts.add_dependency(dep.as_str(), name.as_str())
             }
         }
-        ts.map(|name| self.find_unsafe(name)).collect()
+        Ok(ts.filter_map(|name| self.find(name)).collect())
+    }
+
+    /// Looks for a dependency cycle reachable from `roots`. Returns the path
+    /// of the cycle, which starts and ends with the same package.
+    fn find_cycle<'a>(&'a self, roots: impl IntoIterator<Item = &'a str>) -> Option<Vec<&'a str>> {
+        let mut done: HashSet<&str> = HashSet::new();
+        let mut path: Vec<&str> = vec![];
+        roots
+            .into_iter()
+            .find_map(|root| self.find_cycle_from(root, &mut path, &mut done))
+    }
+
+    fn find_cycle_from<'a>(
+        &'a self,
+        name: &'a str,
+        path: &mut Vec<&'a str>,
+        done: &mut HashSet<&'a str>,
+    ) -> Option<Vec<&'a str>> {
+        if let Some(start) = path.iter().position(|n| *n == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name);
+            return Some(cycle);
+        }
+        if done.contains(name) {
+            return None;
+        }
+        path.push(name);
+        if let Some(package) = self.find(name) {
+            for dep in &package.dependencies {
+                if let Some(cycle) = self.find_cycle_from(dep, path, done) {
+                    return Some(cycle);
+                }
+            }
+        }
+        path.pop();
+        done.insert(name);
+        None
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// Walks the `parents` edges upwards from `node` and collects every path that
+/// reaches one of the `entry_points`. `suffix` holds the path below `node` in
+/// reverse.
+fn collect_paths<'a>(
+    node: &'a str,
+    parents: &HashMap<&str, Vec<&'a str>>,
+    entry_points: &[Name],
+    suffix: &mut Vec<&'a str>,
+    paths: &mut Vec<Vec<&'a str>>,
+) {
+    suffix.push(node);
+    if entry_points.iter().any(|entry_point| entry_point == node) {
+        paths.push(suffix.iter().rev().copied().collect());
+    }
+    for parent in parents.get(node).into_iter().flatten() {
+        collect_paths(parent, parents, entry_points, suffix, paths);
+    }
+    suffix.pop();
+}
+
+fn cycle_error(cycle: &[&str]) -> anyhow::Error {
+    VesselError::DependencyCycle {
+        cycle: cycle.iter().map(|name| name.to_string()).collect(),
+    }
+    .into()
+}
 
-    fn mk_package(name: &str, deps: Vec<&str>) -> Package {
+/// Helpers shared by the tests of all modules
+#[cfg(test)]
+pub(crate) mod test_util {
+    use crate::Package;
+    use std::env;
+    use std::ffi::OsString;
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+
+    /// Creates a package of the `example` GitHub org at version v1.0.0
+    pub(crate) fn mk_package(name: &str, deps: Vec<&str>) -> Package {
         Package {
             name: name.to_string(),
-            repo: "".to_string(),
-            version: "".to_string(),
+            repo: format!("https://github.com/example/{name}"),
+            version: "v1.0.0".to_string(),
             dependencies: deps.into_iter().map(|x| x.to_string()).collect(),
+            sha256: None,
+            submodules: false,
+        }
+    }
+
+    /// The working directory and the environment are shared by all threads,
+    /// so tests that depend on either take turns
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Sets an environment variable until it is dropped
+    struct SetVar(&'static str, Option<OsString>);
+
+    impl SetVar {
+        fn new(name: &'static str, value: &Path) -> SetVar {
+            let previous = env::var_os(name);
+            env::set_var(name, value);
+            SetVar(name, previous)
         }
     }
 
+    impl Drop for SetVar {
+        fn drop(&mut self) {
+            match &self.1 {
+                Some(previous) => env::set_var(self.0, previous),
+                None => env::remove_var(self.0),
+            }
+        }
+    }
+
+    /// Runs `f` with the user-level caches of vessel and Dhall in a temporary
+    /// directory, which is passed to `f`, instead of the user's
+    pub(crate) fn with_caches<R>(f: impl FnOnce(&Path) -> R) -> R {
+        let _lock = ENV.lock().unwrap_or_else(|err| err.into_inner());
+        let caches = tempfile::tempdir().unwrap();
+        let _vessel_home = SetVar::new("VESSEL_HOME", &caches.path().join("vessel"));
+        let _xdg_cache_home = SetVar::new("XDG_CACHE_HOME", caches.path());
+        f(caches.path())
+    }
+
+    /// Runs `f` with `dir` as the working directory and the caches of
+    /// `with_caches`
+    pub(crate) fn in_dir<R>(dir: &Path, f: impl FnOnce() -> R) -> R {
+        struct Restore(PathBuf);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                env::set_current_dir(&self.0).unwrap();
+            }
+        }
+
+        with_caches(|_| {
+            let _restore = Restore(env::current_dir().unwrap());
+            env::set_current_dir(dir).unwrap();
+            f()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_util::{in_dir, mk_package, with_caches};
+    use super::*;
+
     #[test]
     fn it_finds_a_transitive_dependency() {
         let a = mk_package("A", vec!["B"]);
         let b = mk_package("B", vec![]);
         let ps = PackageSet::new(vec![a.clone(), b.clone()]);
-        assert_eq!(vec![&b], ps.transitive_deps(vec!["B".to_string()]));
-        assert_eq!(vec![&a, &b], ps.transitive_deps(vec!["A".to_string()]))
+        assert_eq!(vec![&b], ps.transitive_deps(vec!["B".to_string()]).unwrap());
+        assert_eq!(
+            vec![&a, &b],
+            ps.transitive_deps(vec!["A".to_string()]).unwrap()
+        )
     }
 
     #[test]
@@ -758,9 +2617,877 @@ mod test {
         assert_eq!(
             vec![&a, &b, &c],
             ps.transitive_deps(vec!["A".to_string(), "C".to_string()])
+                .unwrap()
+        );
+
+        assert_eq!(
+            vec![&b, &c],
+            ps.transitive_deps(vec!["C".to_string()]).unwrap()
+        )
+    }
+
+    #[test]
+    fn it_reports_dependency_cycles() {
+        let ps = PackageSet::new(vec![
+            mk_package("A", vec!["B"]),
+            mk_package("B", vec!["C"]),
+            mk_package("C", vec!["A"]),
+            mk_package("D", vec!["D"]),
+            mk_package("E", vec![]),
+        ]);
+        let err = ps.transitive_deps(vec!["B".to_string()]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Found a dependency cycle in the package set: B -> C -> A -> B"
+        );
+        let err = ps.transitive_deps(vec!["D".to_string()]).unwrap_err();
+        assert!(err.to_string().ends_with("D -> D"));
+        assert!(ps.transitive_deps(vec!["E".to_string()]).is_ok());
+
+        let err = ps.topo_sorted().unwrap_err();
+        assert!(err.to_string().ends_with("A -> B -> C -> A"));
+    }
+
+    #[test]
+    fn it_reports_unknown_packages() {
+        let ps = PackageSet::new(vec![
+            mk_package("base", vec![]),
+            mk_package("matchers", vec!["bsae"]),
+        ]);
+        let err = ps
+            .transitive_deps(vec!["matchers".to_string()])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Package \"matchers\" depends on \"bsae\", which wasn't specified in the package set. Did you mean \"base\"?"
+        );
+        let err = ps.transitive_deps(vec!["matcher".to_string()]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Package \"matcher\" wasn't specified in the package set. Did you mean \"matchers\"?"
+        );
+        assert!(matches!(
+            err.downcast_ref::<VesselError>(),
+            Some(VesselError::UnknownPackage { referrer: None, .. })
+        ));
+        let err = ps.transitive_deps(vec!["xyz".to_string()]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Package \"xyz\" wasn't specified in the package set"
+        );
+        assert!(ps.topo_sorted().is_err());
+    }
+
+    #[test]
+    fn it_computes_edit_distances() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("base", "base"), 0);
+        assert_eq!(edit_distance("base", "bsae"), 1);
+        assert_eq!(edit_distance("matcher", "matchers"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn it_sorts_packages_topologically() {
+        let a = mk_package("A", vec!["B", "C"]);
+        let b = mk_package("B", vec!["C"]);
+        let c = mk_package("C", vec![]);
+        let ps = PackageSet::new(vec![a.clone(), b.clone(), c.clone()]);
+        assert_eq!(vec![&c, &b, &a], ps.topo_sorted().unwrap());
+    }
+
+    #[test]
+    fn it_finds_dependency_paths() {
+        let ps = PackageSet::new(vec![
+            mk_package("app", vec!["http", "json"]),
+            mk_package("http", vec!["base"]),
+            mk_package("json", vec!["base", "http"]),
+            mk_package("base", vec![]),
+            mk_package("other", vec!["base"]),
+        ]);
+        let entry_points = vec!["app".to_string(), "json".to_string()];
+        assert_eq!(
+            ps.dependency_paths(&entry_points, "base").unwrap(),
+            vec![
+                vec!["app", "http", "base"],
+                vec!["app", "json", "base"],
+                vec!["app", "json", "http", "base"],
+                vec!["json", "base"],
+                vec!["json", "http", "base"],
+            ]
+        );
+        assert_eq!(
+            ps.dependency_paths(&entry_points, "json").unwrap(),
+            vec![vec!["app", "json"], vec!["json"]]
+        );
+        assert!(ps
+            .dependency_paths(&entry_points, "other")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn it_writes_the_upstream_package_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = |name: &str, version: &str| {
+            format!("{{ name = \"{name}\", repo = \"https://github.com/example/{name}\", version = \"{version}\", dependencies = [ \"base\" ] }}")
+        };
+        let old = format!(
+            "[ {}, {} ]",
+            package("base", "v0.1.0"),
+            package("json", "v1.0.0")
+        );
+        let new = format!(
+            "[ {}, {} ]",
+            package("base", "v0.2.0"),
+            package("json", "v1.0.0")
+        );
+        fs::write(dir.path().join("old.dhall"), &old).unwrap();
+        fs::write(dir.path().join("new.dhall"), &new).unwrap();
+        let package_set_file = dir.path().join("package-set.dhall");
+        fs::write(
+            &package_set_file,
+            "let upstream = ./old.dhall\n\nin  upstream\n",
+        )
+        .unwrap();
+
+        let url = dir.path().join("new.dhall").display().to_string();
+        let hash = hash_dhall_expression(&new).unwrap();
+        // Importing with a hash writes to the Dhall cache
+        let changes = with_caches(|_| write_upstream(&package_set_file, &url, &hash)).unwrap();
+        assert_eq!(
+            changes.unwrap(),
+            vec![PackageChange::Updated {
+                name: "base".to_string(),
+                old_version: "v0.1.0".to_string(),
+                new_version: "v0.2.0".to_string(),
+                old_repo: "https://github.com/example/base".to_string(),
+                new_repo: "https://github.com/example/base".to_string(),
+                added_dependencies: vec![],
+                removed_dependencies: vec![],
+            }]
+        );
+        assert_eq!(
+            fs::read_to_string(&package_set_file).unwrap(),
+            format!("let upstream = {url} {hash}\n\nin  upstream\n")
+        );
+    }
+
+    #[test]
+    fn it_caches_package_sets_under_their_hash() {
+        let package_set = r#"[ { name = "base", repo = "https://github.com/example/base", version = "v0.1.0", dependencies = [] : List Text } ]"#;
+        let hash = hash_dhall_expression(package_set).unwrap();
+        let packages = with_caches(|caches| {
+            cache_dhall_expression(package_set, &hash).unwrap();
+            assert!(caches.join("dhall").read_dir().unwrap().next().is_some());
+            // Resolves from the cache, the release doesn't exist
+            let import = format!(
+                "https://github.com/dfinity/vessel-package-set/releases/download/no-such-release/package-set.dhall {hash}"
+            );
+            load_package_set_import(Path::new("."), &import)
+        });
+        assert_eq!(packages.unwrap()[0].name, "base");
+    }
+
+    #[test]
+    fn it_reads_package_sets_with_optional_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("upstream.dhall"),
+            r#"[ { name = "base", repo = "https://github.com/example/base", version = "v0.1.0", dependencies = [] : List Text } ]"#,
+        )
+        .unwrap();
+        let package_set_file = dir.path().join("package-set.dhall");
+        let template = format!("let upstream = ./upstream.dhall{PACKAGE_SET_TEMPLATE}");
+        fs::write(&package_set_file, &template).unwrap();
+        assert_eq!(read_package_set(&package_set_file).unwrap().len(), 1);
+
+        let addition = r#"[ Package::{
+            , name = "json"
+            , repo = "https://github.com/example/json"
+            , version = "v1.0.0"
+            , dependencies = [ "base" ]
+            , sha256 = Some "sha256:abc"
+            , submodules = True
+            } ]"#;
+        fs::write(
+            &package_set_file,
+            template.replacen("[] : List Package.Type", addition, 1),
+        )
+        .unwrap();
+        let mut packages = read_package_set(&package_set_file).unwrap();
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(packages[0].sha256, None);
+        assert_eq!(packages[1].sha256, Some("sha256:abc".to_string()));
+        assert!(!packages[0].submodules);
+        assert!(packages[1].submodules);
+
+        fs::write(
+            &package_set_file,
+            r#"[ { name = "json", repo = "https://github.com/example/json", version = "v1.0.0", dependencies = [] : List Text, sha265 = Some "sha256:abc" } ]"#,
+        )
+        .unwrap();
+        let err = read_package_set(&package_set_file).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<VesselError>(),
+            Some(VesselError::PackageSetParse { .. })
+        ));
+    }
+
+    #[test]
+    fn it_diffs_package_sets_at_a_revision() {
+        let repo = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let identity = ["-c", "user.name=test", "-c", "user.email=test@example.com"];
+            git(repo.path(), "test", &[&identity[..], args].concat()).unwrap();
+        };
+        let package = |name: &str, version: &str, dependencies: &str| {
+            format!("{{ name = \"{name}\", repo = \"https://github.com/example/{name}\", version = \"{version}\", dependencies = {dependencies} }}")
+        };
+        let sets = repo.path().join("sets");
+        fs::create_dir(&sets).unwrap();
+        let package_set_file = sets.join("package-set.dhall");
+        let write = |base: &str, range: &str| {
+            fs::write(
+                sets.join("upstream.dhall"),
+                format!("[ {} ]", package("base", base, "[] : List Text")),
+            )
+            .unwrap();
+            fs::write(
+                &package_set_file,
+                format!(
+                    "./upstream.dhall # [ {} ]",
+                    package("json", "v1.0.0", &format!("[ \"base@{range}\" ]"))
+                ),
+            )
+            .unwrap();
+        };
+        git(&["init", "-q"]);
+        write("v0.1.0", "^0.1");
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "first"]);
+        write("v0.2.0", "^0.2");
+
+        let old = read_package_set_at(&package_set_file, "HEAD").unwrap();
+        let new = read_package_set(&package_set_file).unwrap();
+        let updated =
+            |name: &str, version: (&str, &str), dependency: (Option<&str>, Option<&str>)| {
+                PackageChange::Updated {
+                    name: name.to_string(),
+                    old_version: version.0.to_string(),
+                    new_version: version.1.to_string(),
+                    old_repo: format!("https://github.com/example/{name}"),
+                    new_repo: format!("https://github.com/example/{name}"),
+                    added_dependencies: dependency.1.into_iter().map(String::from).collect(),
+                    removed_dependencies: dependency.0.into_iter().map(String::from).collect(),
+                }
+            };
+        assert_eq!(
+            diff_package_sets(&old, &new),
+            vec![
+                updated("base", ("v0.1.0", "v0.2.0"), (None, None)),
+                updated(
+                    "json",
+                    ("v1.0.0", "v1.0.0"),
+                    (Some("base@^0.1"), Some("base@^0.2"))
+                ),
+            ]
+        );
+        assert!(read_package_set_at(&package_set_file, "no-such-rev").is_err());
+    }
+
+    #[test]
+    fn it_keeps_compilers_apart_by_platform() {
+        let dir = tempfile::tempdir().unwrap();
+        in_dir(dir.path(), || {
+            let version_dir = Path::new(".vessel/.bin/0.9.0");
+            fs::create_dir_all(version_dir).unwrap();
+            fs::write(version_dir.join("moc"), "").unwrap();
+            fs::write(version_dir.join(PLATFORM_FILE), "aarch64-linux").unwrap();
+
+            let aarch64_linux = "aarch64-linux".parse().unwrap();
+            let x86_64_linux = "x86_64-linux".parse().unwrap();
+            let path = download_compiler("0.9.0", None, Some(aarch64_linux), true, false).unwrap();
+            assert_eq!(path, version_dir.join("aarch64-linux"));
+            assert!(path.join("moc").is_file());
+            assert!(!path.join(PLATFORM_FILE).exists());
+            assert!(!version_dir.join("moc").exists());
+
+            let err =
+                download_compiler("0.9.0", None, Some(x86_64_linux), true, false).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<VesselError>(),
+                Some(VesselError::NotAvailableOffline { .. })
+            ));
+            // Without --platform the install for the host is used
+            let host = download_compiler("0.9.0", None, None, true, false);
+            assert_eq!(
+                host.is_ok(),
+                Platform::host().unwrap() == aarch64_linux,
+                "{:?}",
+                host
+            );
+        });
+    }
+
+    #[test]
+    fn it_checks_compiler_checksums() {
+        let hash = format!("sha256:{}", "ab".repeat(32));
+        let other = format!("sha256:{}", "cd".repeat(32));
+        assert!(check_compiler_checksum(
+            "0.9.0",
+            Some(hash.replace("ab", "AB")),
+            hash.clone(),
+            false
+        )
+        .is_ok());
+        let err = check_compiler_checksum("0.9.0", Some(other), hash.clone(), true).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<VesselError>(),
+            Some(VesselError::CompilerHashMismatch { .. })
+        ));
+        let err = check_compiler_checksum("0.9.0", None, hash.clone(), false).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<VesselError>(),
+            Some(VesselError::CompilerChecksumMissing { found, .. }) if *found == hash
+        ));
+        assert!(check_compiler_checksum("0.9.0", None, hash.clone(), true).is_ok());
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = format!("file://{}/moc.tar.gz", dir.path().display());
+        let checksum_file = dir.path().join("moc.tar.gz.sha256");
+        fs::write(&checksum_file, format!("{}  moc.tar.gz\n", "AB".repeat(32))).unwrap();
+        assert_eq!(fetch_compiler_checksum(&target).unwrap(), Some(hash));
+        fs::write(&checksum_file, "<html>Not Found</html>").unwrap();
+        assert!(fetch_compiler_checksum(&target).is_err());
+        // A mirror on disk without checksum files has no checksum, like a 404
+        fs::remove_file(&checksum_file).unwrap();
+        assert_eq!(fetch_compiler_checksum(&target).unwrap(), None);
+    }
+
+    #[test]
+    fn it_rejects_unknown_manifest_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        in_dir(dir.path(), || {
+            fs::write(
+                "package-set.dhall",
+                "[] : List { name : Text, version : Text, repo : Text, dependencies : List Text }",
+            )
+            .unwrap();
+            for (manifest, valid) in [
+                (
+                    r#"{ dependencies = [] : List Text, compiler = None Text }"#,
+                    true,
+                ),
+                (
+                    r#"{ dependencies = [] : List Text, compiler = Some "0.9.0", compilerSha256 = Some "sha256:abc" }"#,
+                    true,
+                ),
+                (
+                    r#"{ dependencies = [] : List Text, compiler = Some "0.9.0", compilerSha265 = Some "sha256:abc" }"#,
+                    false,
+                ),
+                (r#"{ dependencies = [] : List Text }"#, true),
+                (
+                    r#"{ dependencies = [] : List Text, compiler = Some 9 }"#,
+                    false,
+                ),
+            ] {
+                fs::write("vessel.dhall", manifest).unwrap();
+                match Vessel::new(Path::new("package-set.dhall")) {
+                    Ok(_) => assert!(valid, "{}", manifest),
+                    Err(err) => {
+                        assert!(!valid, "{}", manifest);
+                        assert!(matches!(
+                            err.downcast_ref::<VesselError>(),
+                            Some(VesselError::ManifestParse { .. })
+                        ));
+                    }
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn it_hashes_trees_deterministically() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        fs::write(dir.path().join("a/b/Lib.mo"), "module {}").unwrap();
+        fs::write(dir.path().join("Main.mo"), "actor {}").unwrap();
+        let hash = hash_tree(dir.path()).unwrap();
+        assert!(hash.starts_with("sha256:"));
+        assert_eq!(hash, hash_tree(dir.path()).unwrap());
+
+        fs::write(dir.path().join("a/b/Lib.mo"), "module { }").unwrap();
+        assert_ne!(hash, hash_tree(dir.path()).unwrap());
+
+        assert!(hash_tree(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn it_reads_the_commit_from_tar_balls() {
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let mut builder = tar::Builder::new(vec![]);
+        let record = format!(" comment={commit}\n");
+        let record = format!("{}{}", record.len() + 2, record);
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::XGlobalHeader);
+        header.set_size(record.len() as u64);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "pax_global_header", record.as_bytes())
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(9);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "repo-v1/src/Lib.mo", &b"module {}"[..])
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let found = unpack_tar_ball(&archive[..], dir.path()).unwrap();
+        assert_eq!(found.as_deref(), Some(commit));
+        assert!(dir.path().join("repo-v1/src/Lib.mo").exists());
+        assert!(!dir.path().join("pax_global_header").exists());
+    }
+
+    #[test]
+    fn it_matches_package_hashes() {
+        let mut package = mk_package("A", vec![]);
+        assert!(package.matches_hash("sha256:abcd"));
+
+        package.sha256 = Some("ABCD".to_string());
+        assert!(package.matches_hash("sha256:abcd"));
+        package.sha256 = Some("sha256:abcd".to_string());
+        assert!(package.matches_hash("sha256:abcd"));
+        assert!(!package.matches_hash("sha256:abce"));
+    }
+
+    #[test]
+    fn it_adopts_installs_without_a_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        let install = |name: &str| {
+            let install = dir.path().join(name);
+            fs::create_dir_all(install.join("src")).unwrap();
+            fs::write(install.join("src/Lib.mo"), "module {}").unwrap();
+            install
+        };
+        let mut package = mk_package("base", vec![]);
+        let hash = hash_tree(&install("hash").join("src")).unwrap();
+
+        // Without a lock, sha256 or git checkout it can't be told what it is
+        assert!(check_installed(&package, &install("unknown"), None, false).is_none());
+        let trusted = check_installed(&package, &install("offline"), None, true).unwrap();
+        assert_eq!(
+            (trusted.commit, trusted.hash),
+            ("v1.0.0".to_string(), hash.clone())
+        );
+        assert!(dir.path().join("offline").join(INSTALL_MARKER).exists());
+
+        let cloned = install("cloned");
+        let identity = ["-c", "user.name=test", "-c", "user.email=test@example.com"];
+        git(&cloned, "test", &["init", "-q"]).unwrap();
+        git(&cloned, "test", &["add", "."]).unwrap();
+        git(
+            &cloned,
+            "test",
+            &[&identity[..], &["commit", "-q", "-m", "first"]].concat(),
+        )
+        .unwrap();
+        let head = git(&cloned, "test", &["rev-parse", "HEAD"]).unwrap();
+        let adopted = check_installed(&package, &cloned, None, false).unwrap();
+        assert_eq!(adopted.commit, head.trim());
+
+        let locked = mk_locked_package(&package, "0123abcd".to_string(), hash.clone());
+        assert_eq!(
+            check_installed(&package, &install("locked"), Some(&locked), false),
+            Some(locked.clone())
         );
+        let outdated = LockedPackage {
+            hash: "sha256:00".to_string(),
+            ..locked
+        };
+        assert!(check_installed(&package, &install("outdated"), Some(&outdated), true).is_none());
+
+        package.sha256 = Some(hash.clone());
+        assert!(check_installed(&package, &install("pinned"), None, false).is_some());
+        package.sha256 = Some("sha256:00".to_string());
+        assert!(check_installed(&package, &install("mismatch"), None, true).is_none());
+    }
+
+    #[test]
+    fn it_installs_and_verifies_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        in_dir(dir.path(), || {
+            fs::write(
+                "vessel.dhall",
+                r#"{ dependencies = [ "lib" ], compiler = None Text }"#,
+            )
+            .unwrap();
+            fs::write(
+                "package-set.dhall",
+                r#"[ { name = "lib", repo = "https://github.com/example/lib", version = "v1.0.0", dependencies = [] : List Text }
+                   , { name = "missing", repo = "https://github.com/example/missing", version = "v1.0.0", dependencies = [] : List Text }
+                   ]"#,
+            )
+            .unwrap();
+            // Installed by an earlier run that had network access
+            fs::create_dir_all(".vessel/lib/v1.0.0/src").unwrap();
+            fs::write(".vessel/lib/v1.0.0/src/Lib.mo", "module {}").unwrap();
+
+            let mut vessel = Vessel::new(Path::new("package-set.dhall")).unwrap();
+            vessel.offline = true;
+            let installed = vessel.install_packages(&InstallOptions::default()).unwrap();
+            assert_eq!(installed.len(), 1);
+            assert_eq!(installed[0].path, Path::new(".vessel/lib/v1.0.0/src"));
+            let lockfile = Lockfile::read(Path::new(LOCK_FILE)).unwrap().unwrap();
+            assert_eq!(lockfile.find("lib").unwrap().commit, "v1.0.0");
+
+            let force = InstallOptions {
+                force: true,
+                ..Default::default()
+            };
+            assert!(vessel.install_packages(&force).is_err());
+
+            vessel.manifest.dependencies.push("missing".to_string());
+            let err = vessel
+                .install_packages(&InstallOptions::default())
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<VesselError>(),
+                Some(VesselError::PackagesFailed { .. })
+            ));
+            assert!(format!("{err:#}").contains("offline mode"));
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let moc = dir.path().join("moc");
+                fs::write(&moc, "#!/bin/sh\necho \"$@\" > moc-args\n").unwrap();
+                fs::set_permissions(&moc, fs::Permissions::from_mode(0o755)).unwrap();
+
+                vessel.verify_package(&moc, &None, "lib").unwrap();
+                let args = fs::read_to_string("moc-args").unwrap();
+                assert!(args.starts_with("--check"));
+                assert!(args.contains(".vessel/lib/v1.0.0/src/Lib.mo"));
+
+                let err = vessel.verify_package(&moc, &None, "missing").unwrap_err();
+                assert!(matches!(
+                    err.downcast_ref::<VesselError>(),
+                    Some(VesselError::NotAvailableOffline { .. })
+                ));
+            }
+        });
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_collects_garbage() {
+        let dir = tempfile::tempdir().unwrap();
+        in_dir(dir.path(), || {
+            fs::write(
+                "vessel.dhall",
+                r#"{ dependencies = [ "app" ], compiler = Some "0.9.0" }"#,
+            )
+            .unwrap();
+            fs::write(
+                "package-set.dhall",
+                r#"[ { name = "app", repo = "https://github.com/example/app", version = "v1.0.0", dependencies = [ "lib" ] }
+                   , { name = "lib", repo = "https://github.com/example/lib", version = "v1.0.0", dependencies = [] : List Text }
+                   , { name = "other", repo = "https://github.com/example/other", version = "v1.0.0", dependencies = [] : List Text }
+                   ]"#,
+            )
+            .unwrap();
+            for path in [
+                "app/v1.0.0/src/Lib.mo",
+                "app/v0.9.0/src/Lib.mo",
+                "lib/v1.0.0/src/Lib.mo",
+                "other/v1.0.0/src/Lib.mo",
+                ".tmp/fresh/Lib.mo",
+                ".tmp/stale/Lib.mo",
+                ".bin/0.9.0/x86_64-linux/moc",
+                ".bin/0.8.0/aarch64-linux/moc",
+                ".bin/0.7.0/x86_64-linux/moc",
+                ".bin/0.6.0/moc",
+            ] {
+                let path = Path::new(".vessel").join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, "module {}").unwrap();
+            }
+            let age = |path: &str, days: u64| {
+                let time = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+                for entry in WalkDir::new(Path::new(".vessel").join(path)) {
+                    let file = fs::File::open(entry.unwrap().path()).unwrap();
+                    file.set_modified(time).unwrap();
+                }
+            };
+            age(".tmp/stale", 1);
+            age(".bin", 60);
+            // Used by `verify --version` recently
+            record_use(Path::new(".vessel/.bin/0.8.0/aarch64-linux"));
+
+            let vessel = Vessel::new(Path::new("package-set.dhall")).unwrap();
+            let dry_run = GcOptions {
+                dry_run: true,
+                ..Default::default()
+            };
+            let report = vessel.gc(&dry_run).unwrap();
+            let garbage: Vec<PathBuf> = [
+                ".bin/0.6.0",
+                ".bin/0.7.0/x86_64-linux",
+                ".tmp/stale",
+                "app/v0.9.0",
+                "other/v1.0.0",
+            ]
+            .iter()
+            .map(|path| Path::new(".vessel").join(path))
+            .collect();
+            assert_eq!(report.removed, garbage);
+            assert_eq!(report.reclaimed, 5 * "module {}".len() as u64);
+            assert!(garbage.iter().all(|path| path.exists()));
+
+            let keep_package_set = GcOptions {
+                keep_package_set: true,
+                ..dry_run
+            };
+            let report = vessel.gc(&keep_package_set).unwrap();
+            assert_eq!(report.removed, garbage[..4]);
+
+            let report = vessel.gc(&GcOptions::default()).unwrap();
+            assert_eq!(report.removed, garbage);
+            assert!(garbage.iter().all(|path| !path.exists()));
+            assert!(!Path::new(".vessel/other").exists());
+            assert!(!Path::new(".vessel/.bin/0.7.0").exists());
+            for live in [
+                "app/v1.0.0",
+                "lib/v1.0.0",
+                ".tmp/fresh",
+                ".bin/0.9.0/x86_64-linux",
+                ".bin/0.8.0/aarch64-linux",
+            ] {
+                assert!(Path::new(".vessel").join(live).exists(), "{}", live);
+            }
+        });
+    }
+
+    #[test]
+    fn it_shows_resolved_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        in_dir(dir.path(), || {
+            fs::write(
+                "vessel.dhall",
+                r#"{ dependencies = [ "app" ], compiler = None Text }"#,
+            )
+            .unwrap();
+            fs::write(
+                "package-set.dhall",
+                r#"[ { name = "app", repo = "https://github.com/example/app", version = "v1.0.0", dependencies = [ "lib" ] }
+                   , { name = "lib", repo = "https://github.com/example/lib", version = "^1.1.0", dependencies = [] : List Text }
+                   ]"#,
+            )
+            .unwrap();
+            Lockfile::new(vec![LockedPackage {
+                version: "v1.2.0".to_string(),
+                commit: "v1.2.0".to_string(),
+                hash: "sha256:abc".to_string(),
+                ..mk_locked_package(&mk_package("lib", vec![]), String::new(), String::new())
+            }])
+            .write(Path::new(LOCK_FILE))
+            .unwrap();
+
+            let mut vessel = Vessel::new(Path::new("package-set.dhall")).unwrap();
+            vessel.offline = true;
+            let tree = vessel.dependency_tree(&TreeOptions::default()).unwrap();
+            assert!(tree.contains("lib v1.2.0"), "{}", tree);
+            let graph = vessel.dependency_graph(true).unwrap();
+            assert!(graph
+                .nodes
+                .iter()
+                .any(|node| node.name == "lib" && node.version == "v1.2.0"));
+            assert_eq!(vessel.package_set.find("lib").unwrap().version, "^1.1.0");
+        });
+    }
 
-        assert_eq!(vec![&b, &c], ps.transitive_deps(vec!["C".to_string()]))
+    #[test]
+    fn it_reports_outdated_packages() {
+        let origin = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let identity = ["-c", "user.name=test", "-c", "user.email=test@example.com"];
+            git(origin.path(), "test", &[&identity[..], args].concat()).unwrap();
+        };
+        git(&["init", "-q"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+        for tag in ["v1.0.0", "v1.1.0", "v2.0.0"] {
+            git(&["tag", tag]);
+        }
+        let dir = tempfile::tempdir().unwrap();
+        in_dir(dir.path(), || {
+            fs::write(
+                "vessel.dhall",
+                r#"{ dependencies = [ "lib" ], compiler = None Text }"#,
+            )
+            .unwrap();
+            fs::write(
+                "package-set.dhall",
+                format!(
+                    r#"[ {{ name = "lib", repo = "file://{}", version = "^1.0.0", dependencies = [] : List Text }} ]"#,
+                    origin.path().display()
+                ),
+            )
+            .unwrap();
+
+            let vessel = Vessel::new(Path::new("package-set.dhall")).unwrap();
+            let outdated = vessel.outdated(1).unwrap();
+            assert_eq!(outdated.len(), 1);
+            assert_eq!(outdated[0].current, "v1.1.0");
+            assert_eq!(outdated[0].compatible.as_deref(), Some("v1.1.0"));
+            assert_eq!(outdated[0].latest.as_deref(), Some("v2.0.0"));
+            // The report doesn't change the package set
+            assert_eq!(vessel.package_set.find("lib").unwrap().version, "^1.0.0");
+        });
+    }
+
+    #[test]
+    fn it_serializes_reports_as_json() {
+        let installed = InstalledPackage {
+            name: "base".to_string(),
+            version: "v0.1.0".to_string(),
+            repo: "https://github.com/example/base".to_string(),
+            path: PathBuf::from(".vessel/base/v0.1.0/src"),
+            direct: true,
+        };
+        assert_eq!(
+            serde_json::to_value(&installed).unwrap(),
+            serde_json::json!({
+                "name": "base",
+                "version": "v0.1.0",
+                "repo": "https://github.com/example/base",
+                "path": ".vessel/base/v0.1.0/src",
+                "direct": true
+            })
+        );
+
+        let report = |status| VerificationReport {
+            name: "base".to_string(),
+            status,
+            stderr: "warning".to_string(),
+            error: Some(anyhow::anyhow!("not serialized")),
+        };
+        for (status, name) in [
+            (VerificationStatus::Verified, "verified"),
+            (VerificationStatus::Failed, "failed"),
+            (VerificationStatus::Skipped, "skipped"),
+        ] {
+            assert_eq!(
+                serde_json::to_value(report(status)).unwrap(),
+                serde_json::json!({ "name": "base", "status": name, "stderr": "warning" })
+            );
+        }
+    }
+
+    #[test]
+    fn it_runs_jobs_in_parallel_preserving_order() {
+        let items: Vec<u32> = (0..20).collect();
+        for jobs in [0, 1, 3, 50] {
+            let results = run_parallel(&items, jobs, |x| x * 2);
+            assert_eq!(results, items.iter().map(|x| x * 2).collect::<Vec<_>>());
+        }
+        assert!(run_parallel(&Vec::<u32>::new(), 4, |x| *x).is_empty());
+    }
+
+    #[test]
+    fn it_copies_trees() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("nested")).unwrap();
+        fs::write(src.join("nested/Lib.mo"), "module {}").unwrap();
+        let marker = |hash: &str| {
+            serde_json::to_string(&InstallMarker {
+                commit: "abc".to_string(),
+                hash: hash.to_string(),
+            })
+            .unwrap()
+        };
+        fs::write(src.join(INSTALL_MARKER), marker("sha256:abc")).unwrap();
+
+        let dest = dir.path().join("dest");
+        copy_tree(dir.path(), &src, &dest).unwrap();
+        fs::write(dest.join("nested/Lib.mo"), "module { edited }").unwrap();
+        assert_eq!(
+            fs::read_to_string(src.join("nested/Lib.mo")).unwrap(),
+            "module {}"
+        );
+        fs::write(dest.join("nested/Lib.mo"), "module {}").unwrap();
+        assert_eq!(
+            fs::read_to_string(dest.join("nested/Lib.mo")).unwrap(),
+            "module {}"
+        );
+        assert!(dest.join(INSTALL_MARKER).exists());
+        assert_eq!(hash_tree(&src).unwrap(), hash_tree(&dest).unwrap());
+
+        // When another process copied the package first, its copy is kept
+        copy_tree(dir.path(), &src, &dest).unwrap();
+        fs::write(dest.join(INSTALL_MARKER), marker("sha256:def")).unwrap();
+        assert!(copy_tree(dir.path(), &src, &dest).is_err());
+    }
+
+    #[test]
+    fn it_clones_packages() {
+        let origin = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let identity = ["-c", "user.name=test", "-c", "user.email=test@example.com"];
+            git(origin.path(), "test", &[&identity[..], args].concat())
+                .unwrap()
+                .trim()
+                .to_string()
+        };
+        git(&["init", "-q"]);
+        fs::create_dir(origin.path().join("src")).unwrap();
+        fs::write(origin.path().join("src/Lib.mo"), "module {}").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "first"]);
+        let first = git(&["rev-parse", "HEAD"]);
+        git(&["tag", "v1.0.0"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "second"]);
+
+        let mut package = mk_package("lib", vec![]);
+        package.repo = format!("file://{}", origin.path().display());
+        for version in ["v1.0.0", first.as_str()] {
+            let tmp = tempfile::tempdir().unwrap();
+            let (repo_dir, commit) = clone_package(tmp.path(), &package, version).unwrap();
+            assert_eq!(commit, first);
+            assert!(repo_dir.join("src/Lib.mo").exists());
+        }
+
+        let tmp = tempfile::tempdir().unwrap();
+        let (repo_dir, _) = clone_package(tmp.path(), &package, "v1.0.0").unwrap();
+        let shallow = super::git(&repo_dir, "test", &["rev-parse", "--is-shallow-repository"]);
+        assert_eq!(shallow.unwrap().trim(), "true");
+
+        let gitlink = repo_dir.join("src/vendored/.git");
+        fs::create_dir(gitlink.parent().unwrap()).unwrap();
+        fs::write(&gitlink, "gitdir: ../../.git/modules/vendored").unwrap();
+        remove_gitlinks(&repo_dir).unwrap();
+        assert!(!gitlink.exists());
+        assert!(repo_dir.join(".git").is_dir());
+
+        // When another process installed the package first, its install is kept
+        let dest = tmp.path().join("installed");
+        fs::create_dir(&dest).unwrap();
+        let marker = InstallMarker {
+            commit: "concurrent".to_string(),
+            hash: hash_tree(&repo_dir.join("src")).unwrap(),
+        };
+        fs::write(
+            dest.join(INSTALL_MARKER),
+            serde_json::to_string(&marker).unwrap(),
+        )
+        .unwrap();
+        let installed = fetch_package(&package, None, tmp.path(), &dest, None).unwrap();
+        assert_eq!(installed.commit, "concurrent");
+        assert_eq!(installed.hash, marker.hash);
     }
 
     #[test]
@@ -768,8 +3495,8 @@ mod test {
         // Valid names/versions
         for input in ["a", "A", "a.b", "123", "1.2.3", ".0", ".a", "_"] {
             println!("{input}");
-            assert!(std::panic::catch_unwind(|| validate_name(input)).is_ok());
-            assert!(std::panic::catch_unwind(|| validate_version(input)).is_ok());
+            assert!(validate_name(input).is_ok());
+            assert!(validate_version(input).is_ok());
         }
 
         // Invalid names/versions
@@ -777,8 +3504,8 @@ mod test {
             "", ".", "..", "...", "/", "\\", "a/b", "a\\b", "~", "-", "-a",
         ] {
             println!("{input}");
-            assert!(std::panic::catch_unwind(|| validate_name(input)).is_err());
-            assert!(std::panic::catch_unwind(|| validate_version(input)).is_err());
+            assert!(validate_name(input).is_err());
+            assert!(validate_version(input).is_err());
         }
     }
 }
@@ -0,0 +1,152 @@
+use crate::{Hash, Name, Package, Tag, Url, VesselError};
+use anyhow::{self, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// The file name of the lock file, which lives next to `vessel.dhall`
+pub const LOCK_FILE: &str = "vessel.lock";
+
+const LOCK_FILE_VERSION: u32 = 1;
+
+/// Records the exact commit and source hash a package was installed at
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedPackage {
+    pub name: Name,
+    pub repo: Url,
+    pub version: Tag,
+    pub commit: String,
+    pub hash: Hash,
+}
+
+impl LockedPackage {
+    /// Fails if the package set no longer agrees with what was locked
+    pub fn check_matches(&self, package: &Package) -> Result<()> {
+        if self.repo != package.repo || self.version != package.version {
+            return Err(VesselError::LockOutdated {
+                package: self.name.clone(),
+                locked_version: self.version.clone(),
+                locked_repo: self.repo.clone(),
+                version: package.version.clone(),
+                repo: package.repo.clone(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockfile {
+    version: u32,
+    pub packages: Vec<LockedPackage>,
+}
+
+impl Default for Lockfile {
+    fn default() -> Self {
+        Lockfile {
+            version: LOCK_FILE_VERSION,
+            packages: vec![],
+        }
+    }
+}
+
+impl Lockfile {
+    pub fn new(mut packages: Vec<LockedPackage>) -> Lockfile {
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        Lockfile {
+            version: LOCK_FILE_VERSION,
+            packages,
+        }
+    }
+
+    /// Reads the lock file at `path`, returns `None` if there is none yet
+    pub fn read(path: &Path) -> Result<Option<Lockfile>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path).context(format!(
+            "Failed to read the lock file at {}",
+            path.display()
+        ))?;
+        let lockfile: Lockfile = serde_json::from_str(&contents).context(format!(
+            "Failed to parse the lock file at {}",
+            path.display()
+        ))?;
+        if lockfile.version != LOCK_FILE_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported lock file version {} in {}, expected {}",
+                lockfile.version,
+                path.display(),
+                LOCK_FILE_VERSION
+            ));
+        }
+        Ok(Some(lockfile))
+    }
+
+    /// Writes the lock file to `path`, leaving it untouched if nothing changed
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut contents = serde_json::to_string_pretty(self)?;
+        contents.push('\n');
+        if fs::read_to_string(path).ok().as_deref() == Some(contents.as_str()) {
+            return Ok(());
+        }
+        fs::write(path, contents).context(format!(
+            "Failed to write the lock file at {}",
+            path.display()
+        ))
+    }
+
+    /// Finds a locked package by name
+    pub fn find(&self, name: &str) -> Option<&LockedPackage> {
+        self.packages.iter().find(|p| p.name == name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util;
+
+    fn mk_locked(name: &str, version: &str) -> LockedPackage {
+        LockedPackage {
+            name: name.to_string(),
+            repo: format!("https://github.com/example/{name}"),
+            version: version.to_string(),
+            commit: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            hash: "sha256:00".to_string(),
+        }
+    }
+
+    fn mk_package(name: &str, version: &str) -> Package {
+        Package {
+            version: version.to_string(),
+            ..test_util::mk_package(name, vec![])
+        }
+    }
+
+    #[test]
+    fn it_detects_lock_mismatches() {
+        let locked = mk_locked("base", "v1.0.0");
+        assert!(locked.check_matches(&mk_package("base", "v1.0.0")).is_ok());
+        assert!(locked.check_matches(&mk_package("base", "v1.1.0")).is_err());
+
+        let mut moved = mk_package("base", "v1.0.0");
+        moved.repo = "https://github.com/someone/base".to_string();
+        assert!(locked.check_matches(&moved).is_err());
+    }
+
+    #[test]
+    fn it_round_trips_lock_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(LOCK_FILE);
+        assert_eq!(Lockfile::read(&path).unwrap(), None);
+
+        let lockfile = Lockfile::new(vec![mk_locked("b", "v2"), mk_locked("a", "v1")]);
+        lockfile.write(&path).unwrap();
+        let read = Lockfile::read(&path).unwrap().unwrap();
+        assert_eq!(read, lockfile);
+        assert_eq!(read.packages[0].name, "a");
+        assert_eq!(read.find("b").map(|p| p.version.as_str()), Some("v2"));
+    }
+}
@@ -0,0 +1,394 @@
+//! Edits the `dependencies` list of a `vessel.dhall` file and the `upstream`
+//! binding of a package set file in place, so the rest of the file keeps its
+//! formatting and comments.
+
+use crate::resolve::split_requirement;
+use anyhow::{self, Result};
+use std::ops::Range;
+
+/// A string literal inside the `dependencies` list
+struct Entry {
+    span: Range<usize>,
+    name: String,
+}
+
+/// The `dependencies` list literal of a manifest
+struct DependencyList {
+    /// Position of the opening `[`
+    open: usize,
+    /// Position of the closing `]`
+    close: usize,
+    entries: Vec<Entry>,
+}
+
+/// Appends `names` to the `dependencies` list in `source`
+pub(crate) fn add_dependencies(source: &str, names: &[String]) -> Result<String> {
+    if names.is_empty() {
+        return Ok(source.to_string());
+    }
+    let list = find_dependency_list(source)?;
+    let quoted: Vec<String> = names.iter().map(|name| quote(name)).collect();
+    let mut edited = source.to_string();
+    match list.entries.last() {
+        None => edited.replace_range(
+            list.open..list.close + 1,
+            &format!("[ {} ]", quoted.join(", ")),
+        ),
+        Some(last) => {
+            let separator = separator(source, &list);
+            let insertion: String = quoted
+                .iter()
+                .map(|name| format!("{separator}{name}"))
+                .collect();
+            // Keeps a trailing comment on the same line as the entry it belongs to
+            let position = match line_end(source, last.span.end) {
+                Some(end) if separator.starts_with('\n') => end,
+                _ => last.span.end,
+            };
+            edited.insert_str(position, &insertion);
+        }
+    }
+    Ok(edited)
+}
+
+/// Removes every occurrence of `names` from the `dependencies` list in `source`
+pub(crate) fn remove_dependencies(source: &str, names: &[String]) -> Result<String> {
+    let mut edited = source.to_string();
+    loop {
+        let list = find_dependency_list(&edited)?;
+        let index = match list.entries.iter().position(|e| {
+            names
+                .iter()
+                .any(|name| name == split_requirement(&e.name).0)
+        }) {
+            None => return Ok(edited),
+            Some(index) => index,
+        };
+        if list.entries.len() == 1 {
+            // Empty lists need a type annotation in Dhall
+            let annotated = edited[skip_trivia(&edited, list.close + 1)?..].starts_with(':');
+            let empty = if annotated { "[]" } else { "[] : List Text" };
+            edited.replace_range(list.open..list.close + 1, empty);
+        } else if let Some(line) = own_line(&edited, &list.entries[index].span) {
+            edited.replace_range(line, "");
+        } else if index == 0 {
+            edited.replace_range(list.entries[0].span.start..list.entries[1].span.start, "");
+        } else {
+            edited.replace_range(
+                list.entries[index - 1].span.end..list.entries[index].span.end,
+                "",
+            );
+        }
+    }
+}
+
+/// Picks the text to put in front of a new entry, copying the separator
+/// between the last two entries if possible
+fn separator<'a>(source: &'a str, list: &DependencyList) -> std::borrow::Cow<'a, str> {
+    if let [.., prev, last] = list.entries.as_slice() {
+        let between = &source[prev.span.end..last.span.start];
+        if !between.contains("--") && !between.contains("{-") {
+            return between.into();
+        }
+    }
+    if !source[list.open..list.close].contains('\n') {
+        return ", ".into();
+    }
+    // Multi-line lists get a new line in the leading comma style of `dhall format`
+    let line_start = source[..list.close].rfind('\n').map_or(0, |i| i + 1);
+    let line = &source[line_start..];
+    let indent = &line[..line.len() - line.trim_start().len()];
+    format!("\n{indent}, ").into()
+}
+
+/// Returns the end of the line `position` is on, if there is nothing but
+/// whitespace or a comment between the two
+fn line_end(source: &str, position: usize) -> Option<usize> {
+    let end = source[position..]
+        .find('\n')
+        .map_or(source.len(), |i| position + i);
+    let rest = source[position..end].trim();
+    (rest.is_empty() || rest.starts_with("--")).then_some(end)
+}
+
+/// Returns the full line of an entry that sits on its own line after a
+/// leading comma, including the line break
+fn own_line(source: &str, span: &Range<usize>) -> Option<Range<usize>> {
+    let start = source[..span.start].rfind('\n')? + 1;
+    if source[start..span.start].trim() != "," {
+        return None;
+    }
+    let end = line_end(source, span.end)?;
+    Some(start..(end + 1).min(source.len()))
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Points the `let upstream = ...` binding of a package set file at `import`.
+/// Returns the edited source and the import that was replaced.
+pub(crate) fn replace_upstream(source: &str, import: &str) -> Result<(String, String)> {
+    let start = find_binding(source, "upstream", true)?
+        .ok_or_else(|| anyhow::anyhow!("Could not find a `let upstream = ...` binding"))?;
+    let is_import = ["https://", "http://", "./", "../", "/", "~/", "env:"]
+        .iter()
+        .any(|prefix| source[start..].starts_with(prefix));
+    if !is_import {
+        return Err(anyhow::anyhow!(
+            "The `upstream` binding is not an import, please edit the file by hand"
+        ));
+    }
+    let token_end = |start: usize| {
+        source[start..]
+            .find(char::is_whitespace)
+            .map_or(source.len(), |i| start + i)
+    };
+    let mut end = token_end(start);
+    let hash = skip_trivia(source, end)?;
+    if source[hash..].starts_with("sha256:") {
+        end = token_end(hash);
+    }
+    let mut edited = source.to_string();
+    edited.replace_range(start..end, import);
+    Ok((edited, source[start..end].to_string()))
+}
+
+fn find_dependency_list(source: &str) -> Result<DependencyList> {
+    let open = find_binding(source, "dependencies", false)?.ok_or_else(|| {
+        anyhow::anyhow!("Could not find the `dependencies` field in vessel.dhall")
+    })?;
+    if !source[open..].starts_with('[') {
+        return Err(not_a_list_literal());
+    }
+    parse_list(source, open)
+}
+
+/// Finds the first `label = value` outside of comments and strings, which
+/// also needs to be preceded by `let` if `is_let` is set. Returns the position
+/// of the value.
+fn find_binding(source: &str, label: &str, is_let: bool) -> Result<Option<usize>> {
+    let bytes = source.as_bytes();
+    let mut previous = "";
+    let mut i = 0;
+    while i < bytes.len() {
+        if source[i..].starts_with("--") || source[i..].starts_with("{-") {
+            i = skip_trivia(source, i)?;
+        } else if bytes[i] == b'"' {
+            i = parse_string(source, i)?.1;
+        } else if source[i..].starts_with("''") {
+            i = skip_multiline_string(source, i)?;
+        } else if is_label_char(bytes[i]) {
+            let start = i;
+            while i < bytes.len() && is_label_char(bytes[i]) {
+                i += 1;
+            }
+            let word = &source[start..i];
+            let after_let = previous == "let";
+            previous = word;
+            if word != label || (is_let && !after_let) {
+                continue;
+            }
+            let eq = skip_trivia(source, i)?;
+            if !source[eq..].starts_with('=') {
+                continue;
+            }
+            return Ok(Some(skip_trivia(source, eq + 1)?));
+        } else {
+            i += source[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    Ok(None)
+}
+
+fn parse_list(source: &str, open: usize) -> Result<DependencyList> {
+    let mut entries = vec![];
+    let mut i = open + 1;
+    loop {
+        i = skip_trivia(source, i)?;
+        match source.as_bytes().get(i) {
+            None => return Err(anyhow::anyhow!("Unterminated list in vessel.dhall")),
+            Some(b']') => {
+                return Ok(DependencyList {
+                    open,
+                    close: i,
+                    entries,
+                })
+            }
+            Some(b',') => i += 1,
+            Some(b'"') => {
+                let (name, end) = parse_string(source, i)?;
+                entries.push(Entry { span: i..end, name });
+                i = end;
+            }
+            Some(_) => return Err(not_a_list_literal()),
+        }
+    }
+}
+
+fn not_a_list_literal() -> anyhow::Error {
+    anyhow::anyhow!(
+        "The `dependencies` in vessel.dhall are not a list of plain strings, please edit the file by hand"
+    )
+}
+
+fn is_label_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'-' || c == b'/'
+}
+
+/// Skips whitespace and comments, returns the position of the next token
+fn skip_trivia(source: &str, mut i: usize) -> Result<usize> {
+    loop {
+        let rest = &source[i..];
+        if rest.starts_with("--") {
+            i += rest.find('\n').unwrap_or(rest.len());
+        } else if rest.starts_with("{-") {
+            // Block comments nest in Dhall
+            let mut depth = 0;
+            loop {
+                let rest = &source[i..];
+                if rest.is_empty() {
+                    return Err(anyhow::anyhow!("Unterminated comment"));
+                } else if rest.starts_with("{-") {
+                    depth += 1;
+                    i += 2;
+                } else if rest.starts_with("-}") {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    i += rest.chars().next().map_or(1, char::len_utf8);
+                }
+            }
+        } else if rest.starts_with(|c: char| c.is_whitespace()) {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+        } else {
+            return Ok(i);
+        }
+    }
+}
+
+/// Parses the double quoted string literal starting at `start`, returns its
+/// contents and the position after the closing quote
+fn parse_string(source: &str, start: usize) -> Result<(String, usize)> {
+    let mut contents = String::new();
+    let mut chars = source[start + 1..].char_indices();
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '"' => return Ok((contents, start + 1 + offset + 1)),
+            '\\' => match chars.next() {
+                Some((_, escaped @ ('"' | '\\' | '$' | '/'))) => contents.push(escaped),
+                Some((_, escaped)) => {
+                    contents.push('\\');
+                    contents.push(escaped);
+                }
+                None => break,
+            },
+            c => contents.push(c),
+        }
+    }
+    Err(anyhow::anyhow!("Unterminated string"))
+}
+
+fn skip_multiline_string(source: &str, start: usize) -> Result<usize> {
+    let mut i = start + 2;
+    while let Some(offset) = source[i..].find("''") {
+        i += offset + 2;
+        // `'''` escapes two single quotes
+        if source[i..].starts_with('\'') {
+            i += 1;
+        } else {
+            return Ok(i);
+        }
+    }
+    Err(anyhow::anyhow!("Unterminated string"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn it_adds_dependencies() {
+        let single_line = "{ dependencies = [ \"base\" ], compiler = None Text }";
+        assert_eq!(
+            add_dependencies(single_line, &names(&["foo", "bar"])).unwrap(),
+            "{ dependencies = [ \"base\", \"foo\", \"bar\" ], compiler = None Text }"
+        );
+
+        let formatted = "{ dependencies =\n    [ \"base\"\n    , \"matchers\"\n    ]\n, compiler = None Text\n}\n";
+        assert_eq!(
+            add_dependencies(formatted, &names(&["foo"])).unwrap(),
+            "{ dependencies =\n    [ \"base\"\n    , \"matchers\"\n    , \"foo\"\n    ]\n, compiler = None Text\n}\n"
+        );
+
+        let commented = "-- dependencies = [ \"old\" ]\n{ dependencies = [] : List Text, compiler = Some \"0.6.21\" }";
+        assert_eq!(
+            add_dependencies(commented, &names(&["foo"])).unwrap(),
+            "-- dependencies = [ \"old\" ]\n{ dependencies = [ \"foo\" ] : List Text, compiler = Some \"0.6.21\" }"
+        );
+
+        assert!(add_dependencies(
+            "let deps = [ \"base\" ] in { dependencies = deps }",
+            &names(&["foo"])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn it_replaces_the_upstream_import() {
+        let package_set = "-- let upstream = ./commented.dhall\nlet upstream =\n      https://example.com/old/package-set.dhall sha256:0123\n\nlet additions = [] : List Package\n\nin  upstream # additions\n";
+        let (edited, old) = replace_upstream(
+            package_set,
+            "https://example.com/new/package-set.dhall sha256:4567",
+        )
+        .unwrap();
+        assert_eq!(old, "https://example.com/old/package-set.dhall sha256:0123");
+        assert_eq!(
+            edited,
+            "-- let upstream = ./commented.dhall\nlet upstream =\n      https://example.com/new/package-set.dhall sha256:4567\n\nlet additions = [] : List Package\n\nin  upstream # additions\n"
+        );
+
+        let (edited, old) =
+            replace_upstream("let upstream = ./local.dhall in upstream", "./new.dhall").unwrap();
+        assert_eq!(old, "./local.dhall");
+        assert_eq!(edited, "let upstream = ./new.dhall in upstream");
+
+        assert!(
+            replace_upstream("let upstream = [] : List Text in upstream", "./new.dhall").is_err()
+        );
+        assert!(
+            replace_upstream("let packages = ./local.dhall in packages", "./new.dhall").is_err()
+        );
+    }
+
+    #[test]
+    fn it_removes_dependencies() {
+        let single_line = "{ dependencies = [ \"base\", \"foo\", \"bar\" ], compiler = None Text }";
+        assert_eq!(
+            remove_dependencies(single_line, &names(&["base", "bar"])).unwrap(),
+            "{ dependencies = [ \"foo\" ], compiler = None Text }"
+        );
+        assert_eq!(
+            remove_dependencies(single_line, &names(&["base", "foo", "bar"])).unwrap(),
+            "{ dependencies = [] : List Text, compiler = None Text }"
+        );
+
+        let formatted =
+            "{ dependencies =\n    [ \"base\" -- the standard library\n    , \"foo\"\n    ]\n}\n";
+        assert_eq!(
+            remove_dependencies(formatted, &names(&["foo"])).unwrap(),
+            "{ dependencies =\n    [ \"base\" -- the standard library\n    ]\n}\n"
+        );
+        assert_eq!(
+            remove_dependencies(formatted, &names(&["unknown"])).unwrap(),
+            formatted
+        );
+    }
+}
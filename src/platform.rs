@@ -0,0 +1,179 @@
+//! Maps platforms to the Motoko compiler releases built for them
+
+use crate::{Url, VesselError};
+use anyhow::Result;
+use semver::Version;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Os {
+    Linux,
+    Darwin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+}
+
+/// The platform a compiler release is built for, written as `<arch>-<os>`,
+/// for example `aarch64-darwin`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Platform {
+    pub arch: Arch,
+    pub os: Os,
+}
+
+impl Platform {
+    /// The platform vessel is running on
+    pub fn host() -> Result<Platform> {
+        let os = match std::env::consts::OS {
+            "linux" => Os::Linux,
+            "macos" => Os::Darwin,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Installing the compiler is only supported on Linux or MacOS for now"
+                ))
+            }
+        };
+        let arch = match std::env::consts::ARCH {
+            "x86_64" => Arch::X86_64,
+            "aarch64" => Arch::Aarch64,
+            arch => {
+                return Err(anyhow::anyhow!(
+                    "Installing the compiler is only supported on x86_64 or aarch64, not {}",
+                    arch
+                ))
+            }
+        };
+        Ok(Platform { arch, os })
+    }
+
+    /// The first compiler release published for this platform, `None` if
+    /// every release was. aarch64 builds only exist for later releases.
+    fn first_release(&self) -> Option<Version> {
+        match (self.os, self.arch) {
+            (_, Arch::X86_64) => None,
+            (Os::Darwin, Arch::Aarch64) => Some(Version::new(0, 11, 0)),
+            (Os::Linux, Arch::Aarch64) => Some(Version::new(0, 14, 0)),
+        }
+    }
+
+    /// The URL of the release tarball of the compiler at `version` for this
+    /// platform. Up to 0.6.2 releases were published on download.dfinity.systems
+    /// for x86_64 only, later ones are GitHub release assets.
+    pub(crate) fn compiler_url(&self, version: &str) -> Result<Url> {
+        let semver = Version::parse(version).ok();
+        let released = match (self.first_release(), &semver) {
+            (None, _) => true,
+            (Some(first), Some(semver)) => *semver >= first,
+            (Some(_), None) => false,
+        };
+        if !released {
+            return Err(VesselError::NoCompilerRelease {
+                version: version.to_string(),
+                platform: self.to_string(),
+            }
+            .into());
+        }
+        match semver {
+            Some(semver) if semver > Version::new(0, 6, 2) => {
+                let os = match self.os {
+                    Os::Linux => "Linux",
+                    Os::Darwin => "Darwin",
+                };
+                let arch = match (self.os, self.arch) {
+                    (_, Arch::X86_64) => "x86_64",
+                    (Os::Linux, Arch::Aarch64) => "aarch64",
+                    (Os::Darwin, Arch::Aarch64) => "arm64",
+                };
+                Ok(format!(
+                    "https://github.com/dfinity/motoko/releases/download/{version}/motoko-{os}-{arch}-{version}.tar.gz"
+                ))
+            }
+            _ => Ok(format!(
+                "https://download.dfinity.systems/motoko/{version}/{self}/motoko-{version}.tar.gz"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arch = match self.arch {
+            Arch::X86_64 => "x86_64",
+            Arch::Aarch64 => "aarch64",
+        };
+        let os = match self.os {
+            Os::Linux => "linux",
+            Os::Darwin => "darwin",
+        };
+        write!(f, "{arch}-{os}")
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || {
+            format!(
+                "Unknown platform \"{s}\", expected one of x86_64-linux, aarch64-linux, x86_64-darwin or aarch64-darwin"
+            )
+        };
+        let (arch, os) = s.split_once('-').ok_or_else(unknown)?;
+        let arch = match arch {
+            "x86_64" => Arch::X86_64,
+            "aarch64" | "arm64" => Arch::Aarch64,
+            _ => return Err(unknown()),
+        };
+        let os = match os {
+            "linux" => Os::Linux,
+            "darwin" | "macos" => Os::Darwin,
+            _ => return Err(unknown()),
+        };
+        Ok(Platform { arch, os })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_maps_platforms_to_compiler_releases() {
+        let platform = |s: &str| s.parse::<Platform>().unwrap();
+        assert_eq!(
+            platform("aarch64-linux").compiler_url("0.14.0").unwrap(),
+            "https://github.com/dfinity/motoko/releases/download/0.14.0/motoko-Linux-aarch64-0.14.0.tar.gz"
+        );
+        assert_eq!(
+            platform("arm64-darwin").compiler_url("0.11.0").unwrap(),
+            "https://github.com/dfinity/motoko/releases/download/0.11.0/motoko-Darwin-arm64-0.11.0.tar.gz"
+        );
+        assert_eq!(
+            platform("x86_64-linux").compiler_url("0.9.3").unwrap(),
+            "https://github.com/dfinity/motoko/releases/download/0.9.3/motoko-Linux-x86_64-0.9.3.tar.gz"
+        );
+        assert_eq!(
+            platform("x86_64-darwin").compiler_url("0.6.2").unwrap(),
+            "https://download.dfinity.systems/motoko/0.6.2/x86_64-darwin/motoko-0.6.2.tar.gz"
+        );
+        for (platform_name, version) in [
+            ("aarch64-darwin", "0.6.2"),
+            ("aarch64-darwin", "0.10.4"),
+            ("aarch64-linux", "0.13.7"),
+            ("aarch64-linux", "nightly"),
+        ] {
+            let err = platform(platform_name).compiler_url(version).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<VesselError>(),
+                Some(VesselError::NoCompilerRelease { .. })
+            ));
+        }
+        assert_eq!(platform("arm64-darwin").to_string(), "aarch64-darwin");
+        assert!("x86_64-windows".parse::<Platform>().is_err());
+    }
+}
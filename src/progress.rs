@@ -0,0 +1,209 @@
+//! Reports the progress of installing and verifying packages.
+//!
+//! In interactive mode, which the `vessel` binary enables on terminals, the
+//! progress is shown in a status line at the bottom of stderr that is redrawn
+//! in place. Otherwise it is reported as plain log lines.
+
+use crate::Name;
+use log::info;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// The status line that is currently drawn, empty if there is none
+static STATUS: Mutex<String> = Mutex::new(String::new());
+
+/// Shows progress in a status line instead of log lines
+pub fn set_interactive(interactive: bool) {
+    INTERACTIVE.store(interactive, Ordering::SeqCst);
+}
+
+/// Erases the status line while `f` writes to the terminal and draws it
+/// again afterwards, so log lines don't get mixed up with it
+pub fn suspend_status<R>(f: impl FnOnce() -> R) -> R {
+    let status = STATUS.lock().unwrap();
+    if status.is_empty() {
+        return f();
+    }
+    eprint!("\r\x1b[2K");
+    let result = f();
+    eprint!("{status}");
+    let _ = io::stderr().flush();
+    result
+}
+
+fn draw_status(line: String) {
+    let mut status = STATUS.lock().unwrap();
+    eprint!("\r\x1b[2K{line}");
+    let _ = io::stderr().flush();
+    *status = line;
+}
+
+/// Formats a number of bytes for humans, e.g. "1.5 MiB"
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    if seconds < 60 {
+        format!("{seconds}s")
+    } else {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    }
+}
+
+/// Counts how many of `total` items are `action`, e.g. "installed", and the
+/// bytes downloaded for the items in progress
+pub(crate) struct Progress {
+    action: &'static str,
+    total: usize,
+    finished: AtomicUsize,
+    started: Instant,
+    downloads: Mutex<BTreeMap<Name, u64>>,
+    last_draw: Mutex<Option<Instant>>,
+}
+
+impl Progress {
+    pub(crate) fn new(action: &'static str, total: usize) -> Progress {
+        Progress {
+            action,
+            total,
+            finished: AtomicUsize::new(0),
+            started: Instant::now(),
+            downloads: Mutex::new(BTreeMap::new()),
+            last_draw: Mutex::new(None),
+        }
+    }
+
+    fn counter(&self) -> String {
+        format!(
+            "{}/{} {}, {}",
+            self.finished.load(Ordering::SeqCst),
+            self.total,
+            self.action,
+            format_elapsed(self.started.elapsed())
+        )
+    }
+
+    /// Redraws the status line, at most every 100ms unless `force` is set
+    fn draw(&self, force: bool) {
+        if !INTERACTIVE.load(Ordering::SeqCst) {
+            return;
+        }
+        let mut last_draw = self.last_draw.lock().unwrap();
+        if !force && last_draw.is_some_and(|last| last.elapsed() < Duration::from_millis(100)) {
+            return;
+        }
+        *last_draw = Some(Instant::now());
+        let downloads: Vec<String> = self
+            .downloads
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, bytes)| format!("{} {}", name, format_size(*bytes)))
+            .collect();
+        let mut line = format!("[{}]", self.counter());
+        if !downloads.is_empty() {
+            line.push_str(&format!(" {}", downloads.join(", ")));
+        }
+        draw_status(line);
+    }
+
+    /// Wraps `reader` to count the bytes downloaded for `name`
+    pub(crate) fn reader<'a, R: Read>(&'a self, name: &str, reader: R) -> ProgressReader<'a, R> {
+        self.downloads.lock().unwrap().insert(name.to_string(), 0);
+        self.draw(true);
+        ProgressReader {
+            progress: self,
+            name: name.to_string(),
+            reader,
+        }
+    }
+
+    fn downloaded(&self, name: &str, bytes: usize) {
+        if let Some(total) = self.downloads.lock().unwrap().get_mut(name) {
+            *total += bytes as u64;
+        }
+        self.draw(false);
+    }
+
+    /// Marks `name` as finished
+    pub(crate) fn finish(&self, name: &str) {
+        let downloaded = self.downloads.lock().unwrap().remove(name);
+        let finished = self.finished.fetch_add(1, Ordering::SeqCst) + 1;
+        if INTERACTIVE.load(Ordering::SeqCst) {
+            self.draw(true);
+            return;
+        }
+        if let Some(bytes) = downloaded {
+            info!("Downloaded \"{}\" ({})", name, format_size(bytes));
+        }
+        if finished < self.total {
+            info!("[{}] \"{}\"", self.counter(), name);
+        }
+    }
+}
+
+impl Drop for Progress {
+    /// Removes the status line once the work is done
+    fn drop(&mut self) {
+        let mut status = STATUS.lock().unwrap();
+        if !status.is_empty() {
+            eprint!("\r\x1b[2K");
+            let _ = io::stderr().flush();
+            status.clear();
+        }
+    }
+}
+
+/// Counts the bytes read through it towards a package's download progress
+pub(crate) struct ProgressReader<'a, R> {
+    progress: &'a Progress,
+    name: String,
+    reader: R,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.progress.downloaded(&self.name, read);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_counts_downloaded_bytes() {
+        let progress = Progress::new("installed", 2);
+        let mut reader = progress.reader("base", &b"0123456789"[..]);
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(progress.downloads.lock().unwrap()["base"], 10);
+        progress.finish("base");
+        assert!(progress.downloads.lock().unwrap().is_empty());
+        assert!(progress.counter().starts_with("1/2 installed, "));
+
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_elapsed(Duration::from_secs(75)), "1m 15s");
+    }
+}
@@ -0,0 +1,247 @@
+//! Resolves semver version ranges against the git tags of a package's repo.
+//!
+//! A package's `version` in the package set is a range if it starts with one
+//! of `^`, `~`, `=`, `<`, `>` or `*`, everything else is an exact git ref.
+//! Dependencies in the manifest and the package set can require a range of
+//! versions with `name@range`, for example `base@^1.2`.
+
+use crate::{Name, Tag, VesselError};
+use anyhow::{self, Result};
+use semver::{Version, VersionReq};
+use std::fmt;
+use std::path::Path;
+
+/// Whether a package version is a semver range rather than an exact git ref
+pub fn is_range(version: &str) -> bool {
+    version.starts_with(['^', '~', '=', '<', '>', '*'])
+}
+
+/// Splits a `name@range` dependency into its name and range
+pub fn split_requirement(dependency: &str) -> (&str, Option<&str>) {
+    match dependency.split_once('@') {
+        None => (dependency, None),
+        Some((name, range)) => (name, Some(range)),
+    }
+}
+
+/// Where a version range comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequiredBy {
+    Manifest,
+    PackageSet,
+    Package(Name),
+}
+
+impl fmt::Display for RequiredBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequiredBy::Manifest => write!(f, "vessel.dhall"),
+            RequiredBy::PackageSet => write!(f, "the package set"),
+            RequiredBy::Package(name) => write!(f, "\"{name}\""),
+        }
+    }
+}
+
+/// A version range a package needs to satisfy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
+    pub package: Name,
+    pub range: String,
+    pub required_by: RequiredBy,
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (required by {})", self.range, self.required_by)
+    }
+}
+
+/// Strips the ranges off `name@range` dependencies and returns them as
+/// requirements of `required_by`
+pub(crate) fn split_dependencies(
+    dependencies: &mut [Name],
+    required_by: RequiredBy,
+) -> Vec<Requirement> {
+    let mut requirements = vec![];
+    for dependency in dependencies.iter_mut() {
+        if let (name, Some(range)) = split_requirement(dependency) {
+            requirements.push(Requirement {
+                package: name.to_string(),
+                range: range.to_string(),
+                required_by: required_by.clone(),
+            });
+            dependency.truncate(name.len());
+        }
+    }
+    requirements
+}
+
+/// Parses a git tag like `v1.2.0` as a semver version
+pub(crate) fn parse_tag(tag: &str) -> Option<Version> {
+    Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok()
+}
+
+fn parse_range(range: &str) -> Result<VersionReq> {
+    VersionReq::parse(range).map_err(|_| {
+        VesselError::InvalidVersion {
+            version: range.to_string(),
+        }
+        .into()
+    })
+}
+
+/// Whether the tag `version` satisfies all `requirements`
+pub(crate) fn satisfies(version: &str, requirements: &[&Requirement]) -> Result<bool> {
+    let version = match parse_tag(version) {
+        None => return Ok(false),
+        Some(version) => version,
+    };
+    for requirement in requirements {
+        if !parse_range(&requirement.range)?.matches(&version) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Returns the highest of the `tags` that satisfies all `requirements`
+pub(crate) fn highest_matching(tags: &[Tag], requirements: &[&Requirement]) -> Result<Option<Tag>> {
+    let mut best: Option<(Version, &Tag)> = None;
+    for tag in tags {
+        if satisfies(tag, requirements)? {
+            let version = parse_tag(tag).unwrap();
+            if best.as_ref().is_none_or(|(b, _)| version > *b) {
+                best = Some((version, tag));
+            }
+        }
+    }
+    Ok(best.map(|(_, tag)| tag.clone()))
+}
+
+/// Picks the highest of the `tags` that satisfies all `requirements`, fails
+/// with a `VersionConflict` if there is none
+pub(crate) fn pick_version(
+    package: &str,
+    requirements: &[&Requirement],
+    tags: &[Tag],
+) -> Result<Tag> {
+    match highest_matching(tags, requirements)? {
+        Some(tag) => Ok(tag),
+        None => {
+            let mut available: Vec<(Version, Tag)> = tags
+                .iter()
+                .filter_map(|tag| Some((parse_tag(tag)?, tag.clone())))
+                .collect();
+            available.sort();
+            Err(VesselError::VersionConflict {
+                package: package.to_string(),
+                requirements: requirements.iter().map(|r| r.to_string()).collect(),
+                available: available.into_iter().map(|(_, tag)| tag).collect(),
+            }
+            .into())
+        }
+    }
+}
+
+/// Lists the tags of a git repo without cloning it
+pub(crate) fn list_tags(repo: &str) -> Result<Vec<Tag>> {
+    let output = crate::git_with_env(
+        Path::new("."),
+        repo,
+        &["ls-remote", "--tags", repo],
+        &crate::http::git_env(repo)?,
+    )?;
+    let mut tags: Vec<Tag> = output
+        .lines()
+        .filter_map(|line| line.split('\t').nth(1)?.strip_prefix("refs/tags/"))
+        // Annotated tags are listed a second time, peeled to their commit
+        .map(|tag| tag.trim_end_matches("^{}").to_string())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mk_requirement(range: &str, required_by: RequiredBy) -> Requirement {
+        Requirement {
+            package: "base".to_string(),
+            range: range.to_string(),
+            required_by,
+        }
+    }
+
+    fn tags(tags: &[&str]) -> Vec<Tag> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn it_splits_requirements() {
+        let mut deps = vec!["base@^1.2".to_string(), "matchers".to_string()];
+        let requirements = split_dependencies(&mut deps, RequiredBy::Manifest);
+        assert_eq!(deps, vec!["base", "matchers"]);
+        assert_eq!(
+            requirements,
+            vec![mk_requirement("^1.2", RequiredBy::Manifest)]
+        );
+        assert!(is_range("^1.2") && is_range(">=1.0, <2.0") && is_range("*"));
+        assert!(!is_range("v1.2.0") && !is_range("master"));
+    }
+
+    #[test]
+    fn it_picks_the_highest_compatible_version() {
+        let available = tags(&[
+            "v1.0.0",
+            "v1.2.0",
+            "v1.3.1",
+            "v2.0.0",
+            "v2.1.0-beta",
+            "nightly",
+        ]);
+        let caret = mk_requirement("^1.2", RequiredBy::Manifest);
+        let upper = mk_requirement("<1.3", RequiredBy::Package("http".to_string()));
+        let wide = mk_requirement(">=2", RequiredBy::PackageSet);
+        assert_eq!(
+            pick_version("base", &[&caret], &available).unwrap(),
+            "v1.3.1"
+        );
+        assert_eq!(
+            pick_version("base", &[&caret, &upper], &available).unwrap(),
+            "v1.2.0"
+        );
+        assert_eq!(
+            pick_version("base", &[&wide], &available).unwrap(),
+            "v2.0.0"
+        );
+
+        let err = pick_version("base", &[&caret, &wide], &available).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No version of \"base\" satisfies every requirement:
+  ^1.2 (required by vessel.dhall)
+  >=2 (required by the package set)
+Available versions: v1.0.0, v1.2.0, v1.3.1, v2.0.0, v2.1.0-beta"
+        );
+
+        let invalid = mk_requirement("^one", RequiredBy::Manifest);
+        assert!(pick_version("base", &[&invalid], &available).is_err());
+    }
+
+    #[test]
+    fn it_lists_the_tags_of_local_repos() {
+        let dir = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let identity = ["-c", "user.name=test", "-c", "user.email=test@example.com"];
+            crate::git(dir.path(), "test", &[&identity[..], args].concat()).unwrap();
+        };
+        git(&["init", "-q"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+        git(&["tag", "v1.0.0"]);
+        git(&["tag", "-a", "v1.1.0", "-m", "annotated"]);
+        let repo = format!("file://{}", dir.path().display());
+        assert_eq!(list_tags(&repo).unwrap(), tags(&["v1.0.0", "v1.1.0"]));
+    }
+}
@@ -0,0 +1,121 @@
+//! Renders dependency trees for `vessel tree`
+
+use crate::Package;
+use std::collections::HashSet;
+
+/// Options that control how `Vessel::dependency_tree` renders the tree
+#[derive(Debug, Clone, Default)]
+pub struct TreeOptions {
+    /// Only show the tree starting at this package
+    pub package: Option<String>,
+    /// The maximum depth of the tree, the starting packages are at depth 0
+    pub depth: Option<usize>,
+    /// Show the packages that depend on a package instead of its dependencies
+    pub invert: bool,
+}
+
+struct Renderer<'a, F> {
+    children: F,
+    depth: Option<usize>,
+    expanded: HashSet<&'a str>,
+    out: String,
+}
+
+/// Renders the trees below `roots`, where `children` returns the packages
+/// shown below a package. Subtrees that were shown already are marked with
+/// `(*)` instead of being repeated.
+pub(crate) fn render<'a, F>(roots: &[&'a Package], children: F, depth: Option<usize>) -> String
+where
+    F: Fn(&'a Package) -> Vec<&'a Package>,
+{
+    let mut renderer = Renderer {
+        children,
+        depth,
+        expanded: HashSet::new(),
+        out: String::new(),
+    };
+    for root in roots {
+        renderer.render_node(root, "", "", 0);
+    }
+    renderer.out
+}
+
+impl<'a, F> Renderer<'a, F>
+where
+    F: Fn(&'a Package) -> Vec<&'a Package>,
+{
+    fn render_node(&mut self, package: &'a Package, prefix: &str, indent: &str, level: usize) {
+        let children = if self.depth.is_some_and(|depth| level >= depth) {
+            vec![]
+        } else {
+            (self.children)(package)
+        };
+        let repeated = !children.is_empty() && !self.expanded.insert(&package.name);
+        self.out.push_str(&format!(
+            "{}{} {} ({}){}\n",
+            prefix,
+            package.name,
+            package.version,
+            package.repo,
+            if repeated { " (*)" } else { "" }
+        ));
+        if repeated {
+            return;
+        }
+        for (i, child) in children.iter().enumerate() {
+            let (prefix, child_indent) = if i + 1 == children.len() {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            self.render_node(
+                child,
+                &format!("{indent}{prefix}"),
+                &format!("{indent}{child_indent}"),
+                level + 1,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::mk_package;
+    use crate::PackageSet;
+
+    #[test]
+    fn it_renders_trees() {
+        let ps = PackageSet::new(vec![
+            mk_package("app", vec!["http", "json"]),
+            mk_package("http", vec!["base"]),
+            mk_package("json", vec!["base", "http"]),
+            mk_package("base", vec![]),
+        ]);
+        let children = |package: &Package| {
+            package
+                .dependencies
+                .iter()
+                .map(|dep| &ps.0[dep])
+                .collect::<Vec<_>>()
+        };
+        let app = &ps.0["app"];
+        assert_eq!(
+            render(&[app], children, None),
+            "app v1.0.0 (https://github.com/example/app)
+├── http v1.0.0 (https://github.com/example/http)
+│   └── base v1.0.0 (https://github.com/example/base)
+└── json v1.0.0 (https://github.com/example/json)
+    ├── base v1.0.0 (https://github.com/example/base)
+    └── http v1.0.0 (https://github.com/example/http) (*)
+"
+        );
+        assert_eq!(
+            render(&[app], children, Some(1)),
+            "app v1.0.0 (https://github.com/example/app)
+├── http v1.0.0 (https://github.com/example/http)
+└── json v1.0.0 (https://github.com/example/json)
+"
+        );
+    }
+}